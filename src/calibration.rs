@@ -0,0 +1,6 @@
+#![allow(dead_code)]
+
+pub mod checkerboard;
+pub mod hand_eye;
+pub mod intrinsics;
+mod lm;
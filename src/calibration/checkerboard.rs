@@ -0,0 +1,351 @@
+use std::collections::{HashMap, VecDeque};
+
+use color_eyre::eyre::{eyre, Result};
+use nalgebra::{Matrix2, Point2, Point3, Vector2};
+use zune_jpeg::{
+	zune_core::{colorspace::ColorSpace, options::DecoderOptions},
+	JpegDecoder,
+};
+
+// Number of *inner* corners along each side, and the square size in metres.
+#[derive(Debug, Clone, Copy)]
+pub struct Checkerboard {
+	pub cols: usize,
+	pub rows: usize,
+	pub square: f64,
+}
+
+impl Checkerboard {
+	pub fn len(&self) -> usize {
+		self.cols * self.rows
+	}
+
+	// board frame: origin on the first corner, x along the columns, y along the rows, z into
+	// the board, same order as the detected corners
+	pub fn object_points(&self) -> Vec<Point3<f64>> {
+		(0..self.rows)
+			.flat_map(|r| {
+				(0..self.cols)
+					.map(move |c| Point3::new(c as f64 * self.square, r as f64 * self.square, 0.))
+			})
+			.collect()
+	}
+}
+
+pub struct GrayImage {
+	pub width: usize,
+	pub height: usize,
+	pub data: Vec<f32>,
+}
+
+impl GrayImage {
+	pub fn new(width: usize, height: usize, data: Vec<f32>) -> Self {
+		assert_eq!(data.len(), width * height);
+		Self {
+			width,
+			height,
+			data,
+		}
+	}
+
+	pub fn from_luma(width: usize, height: usize, luma: &[u8]) -> Self {
+		Self::new(width, height, luma.iter().map(|&v| v as f32).collect())
+	}
+
+	pub fn from_jpeg(jpeg: &[u8]) -> Result<Self> {
+		let mut decoder = JpegDecoder::new_with_options(
+			jpeg,
+			DecoderOptions::new_cmd().jpeg_set_out_colorspace(ColorSpace::Luma),
+		);
+		let luma = decoder.decode()?;
+		let (width, height) = decoder
+			.dimensions()
+			.ok_or_else(|| eyre!("JPEG without dimensions"))?;
+		Ok(Self::from_luma(width, height, &luma))
+	}
+
+	fn at(&self, x: usize, y: usize) -> f32 {
+		self.data[y * self.width + x]
+	}
+
+	// bilinear, clamped to the border
+	pub fn sample(&self, x: f64, y: f64) -> f32 {
+		let x = x.clamp(0., (self.width - 1) as f64);
+		let y = y.clamp(0., (self.height - 1) as f64);
+		let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+		let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+		let (fx, fy) = ((x - x0 as f64) as f32, (y - y0 as f64) as f32);
+		let top = self.at(x0, y0) * (1. - fx) + self.at(x1, y0) * fx;
+		let bottom = self.at(x0, y1) * (1. - fx) + self.at(x1, y1) * fx;
+		top * (1. - fy) + bottom * fy
+	}
+
+	pub fn blur(&self, sigma: f32) -> Self {
+		let radius = (3. * sigma).ceil() as isize;
+		let kernel: Vec<f32> = (-radius..=radius)
+			.map(|i| (-(i * i) as f32 / (2. * sigma * sigma)).exp())
+			.collect();
+		let norm: f32 = kernel.iter().sum();
+		let kernel: Vec<f32> = kernel.iter().map(|k| k / norm).collect();
+
+		let (w, h) = (self.width as isize, self.height as isize);
+		let mut tmp = vec![0.; self.data.len()];
+		for y in 0..h {
+			for x in 0..w {
+				let mut acc = 0.;
+				for (k, i) in kernel.iter().zip(-radius..=radius) {
+					let xi = (x + i).clamp(0, w - 1);
+					acc += k * self.data[(y * w + xi) as usize];
+				}
+				tmp[(y * w + x) as usize] = acc;
+			}
+		}
+		let mut out = vec![0.; self.data.len()];
+		for y in 0..h {
+			for x in 0..w {
+				let mut acc = 0.;
+				for (k, i) in kernel.iter().zip(-radius..=radius) {
+					let yi = (y + i).clamp(0, h - 1);
+					acc += k * tmp[(yi * w + x) as usize];
+				}
+				out[(y * w + x) as usize] = acc;
+			}
+		}
+		Self::new(self.width, self.height, out)
+	}
+}
+
+// Finds the inner corners of `board`, refined to sub-pixel accuracy and ordered like
+// `Checkerboard::object_points`. Returns `None` unless every corner was found.
+pub fn find_corners(image: &GrayImage, board: &Checkerboard) -> Option<Vec<Point2<f64>>> {
+	// `order_grid` orients the board from a square of corners
+	if board.cols < 2 || board.rows < 2 {
+		return None;
+	}
+	let smooth = image.blur(1.0);
+	let candidates: Vec<_> = saddle_points(&smooth.blur(1.5), board.len() * 4)
+		.into_iter()
+		.filter(|&p| is_x_junction(&smooth, p, 5.))
+		.collect();
+	let grid = (0..candidates.len().min(30))
+		.filter_map(|seed| grow_grid(&candidates, seed))
+		.find_map(|grid| order_grid(&candidates, &grid, board, image))?;
+	Some(
+		grid.into_iter()
+			.map(|p| refine_corner(&smooth, p, 5))
+			.collect(),
+	)
+}
+
+// X-junctions of the board are saddle points of the intensity, where the Hessian has
+// a strongly negative determinant. Straight edges have one near-zero eigenvalue and stay
+// close to zero.
+fn saddle_points(image: &GrayImage, max_points: usize) -> Vec<Point2<f64>> {
+	let (w, h) = (image.width, image.height);
+	let mut response = vec![0f32; w * h];
+	let mut max_response = 0f32;
+	for y in 1..h - 1 {
+		for x in 1..w - 1 {
+			let c = image.at(x, y);
+			let dxx = image.at(x + 1, y) + image.at(x - 1, y) - 2. * c;
+			let dyy = image.at(x, y + 1) + image.at(x, y - 1) - 2. * c;
+			let dxy = (image.at(x + 1, y + 1) + image.at(x - 1, y - 1)
+				- image.at(x + 1, y - 1)
+				- image.at(x - 1, y + 1))
+				/ 4.;
+			let r = dxy * dxy - dxx * dyy;
+			response[y * w + x] = r;
+			max_response = max_response.max(r);
+		}
+	}
+
+	let threshold = max_response * 0.05;
+	let radius = 5;
+	let mut points = Vec::new();
+	for y in radius..h.saturating_sub(radius) {
+		for x in radius..w.saturating_sub(radius) {
+			let r = response[y * w + x];
+			if r <= threshold {
+				continue;
+			}
+			let is_max = (y - radius..=y + radius).all(|yy| {
+				(x - radius..=x + radius).all(|xx| {
+					let other = response[yy * w + xx];
+					// break ties towards the first pixel in scan order
+					other < r || (other == r && (yy, xx) >= (y, x))
+				})
+			});
+			if is_max {
+				points.push((r, Point2::new(x as f64, y as f64)));
+			}
+		}
+	}
+	points.sort_by(|a, b| b.0.total_cmp(&a.0));
+	points.truncate(max_points);
+	points.into_iter().map(|(_, p)| p).collect()
+}
+
+// Walking around an inner corner crosses dark/light four times, where the T and L junctions
+// on the border of the board (which also show up as saddles) only cross twice.
+fn is_x_junction(image: &GrayImage, p: Point2<f64>, radius: f64) -> bool {
+	const SAMPLES: usize = 16;
+	let ring: Vec<f32> = (0..SAMPLES)
+		.map(|i| {
+			let angle = i as f64 * std::f64::consts::TAU / SAMPLES as f64;
+			image.sample(p.x + radius * angle.cos(), p.y + radius * angle.sin())
+		})
+		.collect();
+	let mean = ring.iter().sum::<f32>() / SAMPLES as f32;
+	let transitions = (0..SAMPLES)
+		.filter(|&i| (ring[i] > mean) != (ring[(i + 1) % SAMPLES] > mean))
+		.count();
+	transitions == 4
+}
+
+// Grows a lattice outwards from `seed`, predicting each neighbour from the step between
+// already found corners so it follows perspective distortion of the board.
+fn grow_grid(candidates: &[Point2<f64>], seed: usize) -> Option<HashMap<(i32, i32), usize>> {
+	let origin = candidates[seed];
+	let mut by_distance: Vec<usize> = (0..candidates.len()).filter(|&i| i != seed).collect();
+	by_distance.sort_by(|&a, &b| {
+		(candidates[a] - origin)
+			.norm_squared()
+			.total_cmp(&(candidates[b] - origin).norm_squared())
+	});
+	let &first = by_distance.first()?;
+	let u = candidates[first] - origin;
+	let &second = by_distance.iter().take(8).find(|&&i| {
+		let v = candidates[i] - origin;
+		let ratio = v.norm() / u.norm();
+		(u.dot(&v) / (u.norm() * v.norm())).abs() < 0.5 && (0.5..2.).contains(&ratio)
+	})?;
+	let v = candidates[second] - origin;
+
+	let mut grid = HashMap::from([((0, 0), seed), ((1, 0), first), ((0, 1), second)]);
+	let mut used: Vec<bool> = vec![false; candidates.len()];
+	used[seed] = true;
+	used[first] = true;
+	used[second] = true;
+
+	let mut queue = VecDeque::from([(0, 0), (1, 0), (0, 1)]);
+	while let Some((i, j)) = queue.pop_front() {
+		let pos = candidates[grid[&(i, j)]];
+		for (di, dj) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+			let next = (i + di, j + dj);
+			if grid.contains_key(&next) {
+				continue;
+			}
+			let step = match grid.get(&(i - di, j - dj)) {
+				Some(&prev) => pos - candidates[prev],
+				None if di != 0 => u * di as f64,
+				None => v * dj as f64,
+			};
+			let predicted = pos + step;
+			let radius = 0.35 * step.norm();
+			let found = (0..candidates.len())
+				.filter(|&k| !used[k])
+				.map(|k| (k, (candidates[k] - predicted).norm()))
+				.filter(|&(_, d)| d < radius)
+				.min_by(|a, b| a.1.total_cmp(&b.1));
+			if let Some((k, _)) = found {
+				used[k] = true;
+				grid.insert(next, k);
+				queue.push_back(next);
+			}
+		}
+	}
+	Some(grid)
+}
+
+// Checks the lattice has exactly the board's shape and puts it into a canonical order:
+// x along the columns, right handed in the image (so z points away from the camera), and
+// the origin next to a dark square where the board's pattern allows telling them apart.
+fn order_grid(
+	candidates: &[Point2<f64>],
+	grid: &HashMap<(i32, i32), usize>,
+	board: &Checkerboard,
+	image: &GrayImage,
+) -> Option<Vec<Point2<f64>>> {
+	if grid.len() != board.len() {
+		return None;
+	}
+	let min_i = grid.keys().map(|k| k.0).min()?;
+	let min_j = grid.keys().map(|k| k.1).min()?;
+	let width = (grid.keys().map(|k| k.0).max()? - min_i + 1) as usize;
+	let height = (grid.keys().map(|k| k.1).max()? - min_j + 1) as usize;
+	let transpose = match (width, height) {
+		(w, h) if (w, h) == (board.cols, board.rows) => false,
+		(w, h) if (h, w) == (board.cols, board.rows) => true,
+		_ => return None,
+	};
+
+	let (cols, rows) = (board.cols, board.rows);
+	let mut points = vec![Point2::origin(); board.len()];
+	for (&(i, j), &k) in grid {
+		let (c, r) = ((i - min_i) as usize, (j - min_j) as usize);
+		let (c, r) = if transpose { (r, c) } else { (c, r) };
+		points[r * cols + c] = candidates[k];
+	}
+
+	let at = |points: &[Point2<f64>], c: usize, r: usize| points[r * cols + c];
+	let u = at(&points, 1, 0) - at(&points, 0, 0);
+	let v = at(&points, 0, 1) - at(&points, 0, 0);
+	if u.perp(&v) < 0. {
+		// mirror the columns
+		for r in 0..rows {
+			points[r * cols..(r + 1) * cols].reverse();
+		}
+	}
+
+	let outer = |points: &[Point2<f64>], c: usize, r: usize, dc: usize, dr: usize| {
+		let p = at(points, c, r);
+		let step_c = p - at(points, dc, r);
+		let step_r = p - at(points, c, dr);
+		image.sample(
+			p.x + 0.5 * (step_c.x + step_r.x),
+			p.y + 0.5 * (step_c.y + step_r.y),
+		)
+	};
+	let origin_square = outer(&points, 0, 0, 1, 1);
+	let far_square = outer(&points, cols - 1, rows - 1, cols - 2, rows - 2);
+	if origin_square > far_square {
+		points.reverse();
+	}
+	Some(points)
+}
+
+// Same idea as OpenCV's cornerSubPix: at the true corner, every gradient in the window is
+// orthogonal to the vector from the corner to that pixel.
+fn refine_corner(image: &GrayImage, corner: Point2<f64>, radius: isize) -> Point2<f64> {
+	let mut q = corner;
+	let sigma = radius as f64 / 2.;
+	for _ in 0..20 {
+		let mut a = Matrix2::zeros();
+		let mut b = Vector2::zeros();
+		for dy in -radius..=radius {
+			for dx in -radius..=radius {
+				let (x, y) = (q.x + dx as f64, q.y + dy as f64);
+				let gx = (image.sample(x + 1., y) - image.sample(x - 1., y)) as f64 / 2.;
+				let gy = (image.sample(x, y + 1.) - image.sample(x, y - 1.)) as f64 / 2.;
+				let weight = (-((dx * dx + dy * dy) as f64) / (2. * sigma * sigma)).exp();
+				let g = Vector2::new(gx, gy);
+				let ggt = g * g.transpose() * weight;
+				a += ggt;
+				b += ggt * Vector2::new(x, y);
+			}
+		}
+		let Some(next) = a.try_inverse().map(|inv| Point2::from(inv * b)) else {
+			break;
+		};
+		// don't let a bad window drag the corner onto a neighbouring one
+		if (next - corner).norm() > radius as f64 {
+			return corner;
+		}
+		let shift = (next - q).norm();
+		q = next;
+		if shift < 0.01 {
+			break;
+		}
+	}
+	q
+}
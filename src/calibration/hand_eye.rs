@@ -0,0 +1,344 @@
+use std::{fs, path::Path, time::Duration};
+
+use color_eyre::eyre::{bail, ensure, Result};
+use nalgebra::{DMatrix, DVector, Matrix3, SymmetricEigen, Vector3};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::{
+	camera::source::FrameSource,
+	geometry::{Pose, Rotation},
+	robot::Robot,
+};
+
+pub trait TargetDetector {
+	// Pose of the calibration target in the camera frame, `None` if it isn't (fully) visible.
+	fn detect(&mut self, frame: &[u8]) -> Result<Option<Pose>>;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HandEyeSample {
+	// flange in the robot base frame
+	pub flange: Pose,
+	// calibration target in the camera frame
+	pub target: Pose,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HandEyeMethod {
+	TsaiLenz,
+	ParkMartin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandEyeResult {
+	pub method: HandEyeMethod,
+	// camera frame expressed in the flange frame, [x, y, z, rx, ry, rz]
+	pub camera_to_flange: [f64; 6],
+	pub samples: usize,
+	// RMS of the AX=XB residuals over every motion pair, in radians and metres
+	pub rotation_rms: f64,
+	pub translation_rms: f64,
+}
+
+impl HandEyeResult {
+	pub fn camera_to_flange(&self) -> Pose {
+		Pose::from_ur(self.camera_to_flange)
+	}
+
+	pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+		fs::write(path, serde_json::to_string_pretty(self)?)?;
+		Ok(())
+	}
+
+	pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+		Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CaptureSettings {
+	pub speed: f64,
+	pub acceleration: f64,
+	pub settle: Duration,
+	// frames still queued from before the move finished, thrown away before detecting
+	pub flush_frames: usize,
+}
+
+impl Default for CaptureSettings {
+	fn default() -> Self {
+		Self {
+			speed: 0.1,
+			acceleration: 0.3,
+			settle: Duration::from_millis(500),
+			flush_frames: 4,
+		}
+	}
+}
+
+pub async fn capture_samples<D: TargetDetector>(
+	robot: &mut Robot,
+	source: &mut impl FrameSource,
+	poses: &[Pose],
+	detector: &mut D,
+	settings: CaptureSettings,
+) -> Result<Vec<HandEyeSample>> {
+	let mut samples = Vec::with_capacity(poses.len());
+	for (i, &pose) in poses.iter().enumerate() {
+		robot
+			.move_l(pose, settings.speed, settings.acceleration)
+			.await?;
+		sleep(settings.settle).await;
+		for _ in 0..settings.flush_frames {
+			source.read_frame(|_, _| ()).await?;
+		}
+		let (target, info) = source
+			.read_frame(|data, info| (detector.detect(data), info))
+			.await?;
+		match target? {
+			Some(target) => {
+				// measured rather than commanded, the two differ by the controller's accuracy
+				let flange = robot.pose_at(info.timestamp).await?;
+				samples.push(HandEyeSample { flange, target })
+			}
+			None => println!("Calibration target not found at pose {i}, skipping"),
+		}
+	}
+	Ok(samples)
+}
+
+// Small set of poses around `center` (which should look at the target), tilting the tool
+// about its own x and y axes and rolling it about z. Hand-eye needs rotations about at
+// least two non-parallel axes, and gets better conditioned the larger they are.
+pub fn sample_poses(center: Pose, tilt: f64, roll: f64) -> Vec<Pose> {
+	let mut poses = vec![center];
+	for (rx, ry) in [
+		(1., 0.),
+		(-1., 0.),
+		(0., 1.),
+		(0., -1.),
+		(1., 1.),
+		(-1., -1.),
+	] {
+		for rz in [-roll, roll] {
+			let rotation = Rotation::from_rpy([rx * tilt, ry * tilt, rz]);
+			poses.push(center.compose(&Pose::new(Vector3::zeros(), rotation)));
+		}
+	}
+	poses
+}
+
+// Solves AX = XB for X = camera to flange, with A the flange motion and B the camera
+// motion between every pair of samples.
+pub fn solve(samples: &[HandEyeSample], method: HandEyeMethod) -> Result<HandEyeResult> {
+	ensure!(
+		samples.len() >= 3,
+		"Hand-eye calibration needs at least 3 samples, got {}",
+		samples.len()
+	);
+
+	let motions = relative_motions(samples);
+	let rotation = match method {
+		HandEyeMethod::TsaiLenz => tsai_lenz_rotation(&motions)?,
+		HandEyeMethod::ParkMartin => park_martin_rotation(&motions)?,
+	};
+	let translation = solve_translation(&motions, &rotation)?;
+	let x = Pose::new(translation, rotation);
+
+	let (mut rot_sq, mut trans_sq) = (0., 0.);
+	for (a, b) in &motions {
+		let ax = a.compose(&x);
+		let xb = x.compose(b);
+		rot_sq += ax.rotation_distance(&xb).powi(2);
+		trans_sq += ax.translation_distance(&xb).powi(2);
+	}
+	let n = motions.len() as f64;
+
+	Ok(HandEyeResult {
+		method,
+		camera_to_flange: x.to_ur(),
+		samples: samples.len(),
+		rotation_rms: (rot_sq / n).sqrt(),
+		translation_rms: (trans_sq / n).sqrt(),
+	})
+}
+
+// Drives the arm through `sample_poses` around `centre`, which should have the target in
+// view, moves back to `centre` and solves with both methods, reporting their residuals.
+// Returns the result with the lower translation residual, for saving.
+pub async fn calibrate<D: TargetDetector>(
+	robot: &mut Robot,
+	source: &mut impl FrameSource,
+	detector: &mut D,
+	centre: Pose,
+	tilt: f64,
+	roll: f64,
+	settings: CaptureSettings,
+) -> Result<HandEyeResult> {
+	let poses = sample_poses(centre, tilt, roll);
+	let samples = capture_samples(robot, source, &poses, detector, settings).await?;
+	robot
+		.move_l(centre, settings.speed, settings.acceleration)
+		.await?;
+	println!("Target found at {} of {} poses", samples.len(), poses.len());
+
+	let mut best: Option<HandEyeResult> = None;
+	for method in [HandEyeMethod::TsaiLenz, HandEyeMethod::ParkMartin] {
+		let result = solve(&samples, method)?;
+		println!(
+			"{method:?}: {:.3} mrad, {:.2} mm RMS, camera to flange {:?}",
+			result.rotation_rms * 1000.,
+			result.translation_rms * 1000.,
+			result.camera_to_flange
+		);
+		if best
+			.as_ref()
+			.is_none_or(|b| result.translation_rms < b.translation_rms)
+		{
+			best = Some(result);
+		}
+	}
+	Ok(best.expect("both methods were solved"))
+}
+
+// Base to target is fixed, so F_i X C_i = F_j X C_j, giving
+// A = F_i^-1 F_j and B = C_i C_j^-1.
+fn relative_motions(samples: &[HandEyeSample]) -> Vec<(Pose, Pose)> {
+	let mut motions = Vec::new();
+	for (i, si) in samples.iter().enumerate() {
+		for sj in &samples[i + 1..] {
+			let a = si.flange.inverse().compose(&sj.flange);
+			let b = si.target.compose(&sj.target.inverse());
+			// near-pure translations carry no rotation information and only add noise
+			if a.rotation.angle() > 1e-3 {
+				motions.push((a, b));
+			}
+		}
+	}
+	motions
+}
+
+fn skew(v: &Vector3<f64>) -> Matrix3<f64> {
+	Matrix3::new(0., -v.z, v.y, v.z, 0., -v.x, -v.y, v.x, 0.)
+}
+
+// modified Rodrigues vector, 2 sin(theta / 2) * axis
+fn rodrigues(rotation: &Rotation) -> Vector3<f64> {
+	let q = rotation.unit_quaternion();
+	// pick the hemisphere with w >= 0 so theta stays in [0, pi]
+	let sign = if q.w < 0. { -2. } else { 2. };
+	Vector3::new(q.i, q.j, q.k) * sign
+}
+
+fn tsai_lenz_rotation(motions: &[(Pose, Pose)]) -> Result<Rotation> {
+	let n = motions.len();
+	let mut lhs = DMatrix::zeros(3 * n, 3);
+	let mut rhs = DVector::zeros(3 * n);
+	for (i, (a, b)) in motions.iter().enumerate() {
+		let pa = rodrigues(&a.rotation);
+		let pb = rodrigues(&b.rotation);
+		lhs.fixed_view_mut::<3, 3>(3 * i, 0)
+			.copy_from(&skew(&(pa + pb)));
+		rhs.fixed_view_mut::<3, 1>(3 * i, 0).copy_from(&(pb - pa));
+	}
+	let p_prime = least_squares(lhs, rhs)?;
+	let p_prime = Vector3::new(p_prime[0], p_prime[1], p_prime[2]);
+	let p = p_prime * (2. / (1. + p_prime.norm_squared()).sqrt());
+	let p_sq = p.norm_squared();
+	let m = Matrix3::identity() * (1. - p_sq / 2.)
+		+ (p * p.transpose() + skew(&p) * (4. - p_sq).sqrt()) * 0.5;
+	Ok(Rotation::from_matrix(&m))
+}
+
+fn park_martin_rotation(motions: &[(Pose, Pose)]) -> Result<Rotation> {
+	// log(R_A) = R_X log(R_B), so R_X is the orthogonal Procrustes solution
+	// R_X = (M^T M)^(-1/2) M^T with M = sum(beta * alpha^T)
+	let mut m = Matrix3::zeros();
+	for (a, b) in motions {
+		let alpha = Vector3::from(a.rotation.to_rotation_vector());
+		let beta = Vector3::from(b.rotation.to_rotation_vector());
+		m += beta * alpha.transpose();
+	}
+	let eigen = SymmetricEigen::new(m.transpose() * m);
+	if eigen.eigenvalues.min() < 1e-12 {
+		bail!("Hand-eye motions are degenerate, rotate about at least two non-parallel axes");
+	}
+	let inv_sqrt = eigen.eigenvectors
+		* Matrix3::from_diagonal(&eigen.eigenvalues.map(|l| 1. / l.sqrt()))
+		* eigen.eigenvectors.transpose();
+	Ok(Rotation::from_matrix(&(inv_sqrt * m.transpose())))
+}
+
+// (R_A - I) t_X = R_X t_B - t_A, stacked over every motion
+fn solve_translation(motions: &[(Pose, Pose)], rotation: &Rotation) -> Result<Vector3<f64>> {
+	let n = motions.len();
+	let mut lhs = DMatrix::zeros(3 * n, 3);
+	let mut rhs = DVector::zeros(3 * n);
+	for (i, (a, b)) in motions.iter().enumerate() {
+		lhs.fixed_view_mut::<3, 3>(3 * i, 0)
+			.copy_from(&(a.rotation.matrix() - Matrix3::identity()));
+		rhs.fixed_view_mut::<3, 1>(3 * i, 0)
+			.copy_from(&(rotation.apply(&b.translation) - a.translation));
+	}
+	let t = least_squares(lhs, rhs)?;
+	Ok(Vector3::new(t[0], t[1], t[2]))
+}
+
+fn least_squares(lhs: DMatrix<f64>, rhs: DVector<f64>) -> Result<DVector<f64>> {
+	match lhs.svd(true, true).solve(&rhs, 1e-12) {
+		Ok(x) => Ok(x),
+		Err(e) => bail!("Least squares solve failed: {e}"),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::f64::consts::PI;
+
+	use super::*;
+
+	// Flange poses from `sample_poses`, and what a camera at `x` on the flange sees of a
+	// fixed target: base to target = F X C, so C = (F X)^-1 (base to target). `noise`
+	// perturbs each target pose by up to that many radians and metres.
+	fn samples(x: &Pose, noise: f64) -> Vec<HandEyeSample> {
+		let base_to_target = Pose::from_ur([0.5, 0.1, 0., 0., 0., 0.3]);
+		let centre = Pose::from_ur([0.45, 0.05, 0.35, PI, 0., 0.]);
+		sample_poses(centre, 0.3, 0.4)
+			.into_iter()
+			.enumerate()
+			.map(|(i, flange)| {
+				let wobble = |k: f64| noise * (i as f64 * 1.7 + k).sin();
+				let error = Pose::from_ur([
+					wobble(0.),
+					wobble(1.),
+					wobble(2.),
+					wobble(3.),
+					wobble(4.),
+					wobble(5.),
+				]);
+				let target = flange
+					.compose(x)
+					.inverse()
+					.compose(&base_to_target)
+					.compose(&error);
+				HandEyeSample { flange, target }
+			})
+			.collect()
+	}
+
+	#[test]
+	fn recovers_camera_to_flange() {
+		let x = Pose::from_ur([0.03, -0.05, 0.08, 0.1, -0.2, 1.5]);
+		for (noise, tolerance) in [(0., 1e-6), (1e-4, 2e-3)] {
+			for method in [HandEyeMethod::TsaiLenz, HandEyeMethod::ParkMartin] {
+				let result = solve(&samples(&x, noise), method).unwrap();
+				let found = result.camera_to_flange();
+				assert!(
+					found.translation_distance(&x) < tolerance
+						&& found.rotation_distance(&x) < tolerance,
+					"{method:?} with noise {noise}: {found:?}, expected {x:?}"
+				);
+			}
+		}
+	}
+}
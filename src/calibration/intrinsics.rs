@@ -0,0 +1,565 @@
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{bail, ensure, Result};
+use nalgebra::{DMatrix, DVector, Matrix3, Point2, Point3, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::{Pose, Rotation};
+
+use super::{
+	checkerboard::{find_corners, Checkerboard, GrayImage},
+	hand_eye::TargetDetector,
+	lm::{self, LmSettings},
+};
+
+// Pinhole camera with Brown-Conrady distortion, distortion = [k1, k2, p1, p2, k3]
+// (same order as OpenCV).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraIntrinsics {
+	pub width: u32,
+	pub height: u32,
+	pub fx: f64,
+	pub fy: f64,
+	pub cx: f64,
+	pub cy: f64,
+	pub distortion: [f64; 5],
+}
+
+impl CameraIntrinsics {
+	pub fn camera_matrix(&self) -> Matrix3<f64> {
+		Matrix3::new(self.fx, 0., self.cx, 0., self.fy, self.cy, 0., 0., 1.)
+	}
+
+	// normalised image coordinates (x/z, y/z) to distorted ones
+	pub fn distort(&self, p: Point2<f64>) -> Point2<f64> {
+		let [k1, k2, p1, p2, k3] = self.distortion;
+		let (x, y) = (p.x, p.y);
+		let r2 = x * x + y * y;
+		let radial = 1. + r2 * (k1 + r2 * (k2 + r2 * k3));
+		Point2::new(
+			x * radial + 2. * p1 * x * y + p2 * (r2 + 2. * x * x),
+			y * radial + p1 * (r2 + 2. * y * y) + 2. * p2 * x * y,
+		)
+	}
+
+	pub fn project(&self, p: &Point3<f64>) -> Point2<f64> {
+		let d = self.distort(Point2::new(p.x / p.z, p.y / p.z));
+		Point2::new(self.fx * d.x + self.cx, self.fy * d.y + self.cy)
+	}
+
+	// pixel to undistorted normalised coordinates, by fixed point iteration on the
+	// distortion model
+	pub fn undistort(&self, pixel: Point2<f64>) -> Point2<f64> {
+		let distorted = Point2::new((pixel.x - self.cx) / self.fx, (pixel.y - self.cy) / self.fy);
+		let mut p = distorted;
+		for _ in 0..20 {
+			let error = self.distort(p) - distorted;
+			p -= error;
+			if error.norm() < 1e-12 {
+				break;
+			}
+		}
+		p
+	}
+
+	fn to_params(self) -> [f64; 9] {
+		let [k1, k2, p1, p2, k3] = self.distortion;
+		[self.fx, self.fy, self.cx, self.cy, k1, k2, p1, p2, k3]
+	}
+
+	fn with_params(self, p: &[f64]) -> Self {
+		Self {
+			fx: p[0],
+			fy: p[1],
+			cx: p[2],
+			cy: p[3],
+			distortion: [p[4], p[5], p[6], p[7], p[8]],
+			..self
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageError {
+	pub name: String,
+	// RMS reprojection error in pixels
+	pub rms: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntrinsicCalibration {
+	pub intrinsics: CameraIntrinsics,
+	pub rms: f64,
+	pub images: Vec<ImageError>,
+	// images the board wasn't found in
+	pub rejected: Vec<String>,
+}
+
+impl IntrinsicCalibration {
+	pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+		fs::write(path, serde_json::to_string_pretty(self)?)?;
+		Ok(())
+	}
+
+	pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+		Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+	}
+}
+
+pub fn calibrate_dir(board: &Checkerboard, dir: impl AsRef<Path>) -> Result<IntrinsicCalibration> {
+	let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+		.map(|entry| entry.map(|e| e.path()))
+		.collect::<Result<_, _>>()?;
+	paths.retain(|p| {
+		p.extension()
+			.and_then(|e| e.to_str())
+			.is_some_and(|e| matches!(e.to_ascii_lowercase().as_str(), "jpg" | "jpeg"))
+	});
+	paths.sort();
+
+	let mut images = Vec::with_capacity(paths.len());
+	for path in paths {
+		let Some(name) = path.file_name() else {
+			bail!("{} has no file name", path.display());
+		};
+		let name = name.to_string_lossy().into_owned();
+		images.push((name, GrayImage::from_jpeg(&fs::read(&path)?)?));
+	}
+	calibrate(board, &images)
+}
+
+pub fn calibrate(
+	board: &Checkerboard,
+	images: &[(String, GrayImage)],
+) -> Result<IntrinsicCalibration> {
+	let Some((_, first)) = images.first() else {
+		bail!("No images to calibrate from");
+	};
+	let (width, height) = (first.width, first.height);
+
+	let mut names = Vec::new();
+	let mut views = Vec::new();
+	let mut rejected = Vec::new();
+	for (name, image) in images {
+		ensure!(
+			(image.width, image.height) == (width, height),
+			"{name} is {}x{}, expected {width}x{height}",
+			image.width,
+			image.height
+		);
+		match find_corners(image, board) {
+			Some(corners) => {
+				names.push(name.clone());
+				views.push(corners);
+			}
+			None => rejected.push(name.clone()),
+		}
+	}
+	ensure!(
+		views.len() >= 3,
+		"Checkerboard only found in {} images, need at least 3",
+		views.len()
+	);
+
+	let object = board.object_points();
+	let homographies = views
+		.iter()
+		.map(|corners| {
+			let plane: Vec<_> = object.iter().map(|p| Point2::new(p.x, p.y)).collect();
+			homography(&plane, corners)
+		})
+		.collect::<Result<Vec<_>>>()?;
+
+	let initial = CameraIntrinsics {
+		width: width as u32,
+		height: height as u32,
+		..zhang(&homographies)?
+	};
+	let Some(k_inv) = initial.camera_matrix().try_inverse() else {
+		bail!("Closed form calibration gave a singular camera matrix");
+	};
+	let poses: Vec<Pose> = homographies
+		.iter()
+		.map(|h| pose_from_homography(&(k_inv * h)))
+		.collect();
+
+	let mut params = initial.to_params().to_vec();
+	for pose in &poses {
+		params.extend(pose.to_ur());
+	}
+	let residuals = |p: &DVector<f64>| {
+		let intrinsics = initial.with_params(&p.as_slice()[..9]);
+		let mut r = DVector::zeros(views.len() * object.len() * 2);
+		for (v, corners) in views.iter().enumerate() {
+			let pose = Pose::from_ur(view_params(p, v));
+			for (i, (obj, corner)) in object.iter().zip(corners).enumerate() {
+				let projected = intrinsics.project(&pose.transform_point(obj));
+				let idx = 2 * (v * object.len() + i);
+				r[idx] = projected.x - corner.x;
+				r[idx + 1] = projected.y - corner.y;
+			}
+		}
+		r
+	};
+	let params = lm::minimize(DVector::from_vec(params), residuals, LmSettings::default());
+	let intrinsics = initial.with_params(&params.as_slice()[..9]);
+
+	let r = residuals(&params);
+	let per_view = object.len() * 2;
+	let images = names
+		.into_iter()
+		.enumerate()
+		.map(|(v, name)| {
+			let view = r.rows(v * per_view, per_view);
+			ImageError {
+				name,
+				rms: (view.norm_squared() / object.len() as f64).sqrt(),
+			}
+		})
+		.collect();
+
+	Ok(IntrinsicCalibration {
+		intrinsics,
+		rms: (r.norm_squared() / (views.len() * object.len()) as f64).sqrt(),
+		images,
+		rejected,
+	})
+}
+
+fn view_params(p: &DVector<f64>, view: usize) -> [f64; 6] {
+	let mut out = [0.; 6];
+	out.copy_from_slice(&p.as_slice()[9 + 6 * view..15 + 6 * view]);
+	out
+}
+
+// Normalised DLT, maps `from` onto `to`.
+fn homography(from: &[Point2<f64>], to: &[Point2<f64>]) -> Result<Matrix3<f64>> {
+	ensure!(from.len() >= 4, "Need at least 4 points for a homography");
+	let t_from = normalisation(from);
+	let t_to = normalisation(to);
+
+	let mut a = DMatrix::zeros(2 * from.len(), 9);
+	for (i, (f, t)) in from.iter().zip(to).enumerate() {
+		let f = t_from * f.to_homogeneous();
+		let t = t_to * t.to_homogeneous();
+		let (x, y) = (f.x / f.z, f.y / f.z);
+		let (u, v) = (t.x / t.z, t.y / t.z);
+		let rows = [
+			[-x, -y, -1., 0., 0., 0., u * x, u * y, u],
+			[0., 0., 0., -x, -y, -1., v * x, v * y, v],
+		];
+		for (k, row) in rows.iter().enumerate() {
+			for (j, &value) in row.iter().enumerate() {
+				a[(2 * i + k, j)] = value;
+			}
+		}
+	}
+	let h = smallest_singular_vector(a)?;
+	let h = Matrix3::from_row_slice(h.as_slice());
+	let Some(t_to_inv) = t_to.try_inverse() else {
+		bail!("Degenerate points for homography");
+	};
+	let h = t_to_inv * h * t_from;
+	Ok(h / h[(2, 2)])
+}
+
+// translates the centroid to the origin and scales the mean distance to sqrt(2)
+fn normalisation(points: &[Point2<f64>]) -> Matrix3<f64> {
+	let n = points.len() as f64;
+	let centroid = points
+		.iter()
+		.fold(Vector3::zeros(), |acc, p| acc + Vector3::new(p.x, p.y, 0.))
+		/ n;
+	let mean_dist = points
+		.iter()
+		.map(|p| ((p.x - centroid.x).powi(2) + (p.y - centroid.y).powi(2)).sqrt())
+		.sum::<f64>()
+		/ n;
+	let s = std::f64::consts::SQRT_2 / mean_dist.max(f64::EPSILON);
+	Matrix3::new(s, 0., -s * centroid.x, 0., s, -s * centroid.y, 0., 0., 1.)
+}
+
+fn smallest_singular_vector(a: DMatrix<f64>) -> Result<DVector<f64>> {
+	// the SVD of a wide matrix doesn't give the full V, so pad to square
+	let a = if a.nrows() < a.ncols() {
+		let cols = a.ncols();
+		a.resize_vertically(cols, 0.)
+	} else {
+		a
+	};
+	let svd = a.svd(false, true);
+	let Some(v_t) = svd.v_t else {
+		bail!("SVD failed");
+	};
+	let (idx, _) = svd.singular_values.argmin();
+	Ok(v_t.row(idx).transpose())
+}
+
+// Zhang's closed form: each homography gives two linear constraints on the image of the
+// absolute conic B = K^-T K^-1.
+fn zhang(homographies: &[Matrix3<f64>]) -> Result<CameraIntrinsics> {
+	let v = |h: &Matrix3<f64>, i: usize, j: usize| {
+		let (hi, hj) = (h.column(i), h.column(j));
+		[
+			hi[0] * hj[0],
+			hi[0] * hj[1] + hi[1] * hj[0],
+			hi[1] * hj[1],
+			hi[2] * hj[0] + hi[0] * hj[2],
+			hi[2] * hj[1] + hi[1] * hj[2],
+			hi[2] * hj[2],
+		]
+	};
+	let mut a = DMatrix::zeros(2 * homographies.len(), 6);
+	for (i, h) in homographies.iter().enumerate() {
+		let v12 = v(h, 0, 1);
+		let v11 = v(h, 0, 0);
+		let v22 = v(h, 1, 1);
+		for j in 0..6 {
+			a[(2 * i, j)] = v12[j];
+			a[(2 * i + 1, j)] = v11[j] - v22[j];
+		}
+	}
+	let b = smallest_singular_vector(a)?;
+	let (b11, b12, b22, b13, b23, b33) = (b[0], b[1], b[2], b[3], b[4], b[5]);
+
+	let denom = b11 * b22 - b12 * b12;
+	let cy = (b12 * b13 - b11 * b23) / denom;
+	let lambda = b33 - (b13 * b13 + cy * (b12 * b13 - b11 * b23)) / b11;
+	let fx = (lambda / b11).sqrt();
+	let fy = (lambda * b11 / denom).sqrt();
+	let skew = -b12 * fx * fx * fy / lambda;
+	let cx = skew * cy / fy - b13 * fx * fx / lambda;
+	if ![fx, fy, cx, cy].iter().all(|v| v.is_finite()) {
+		bail!("Closed form calibration failed, try more varied board orientations");
+	}
+
+	// skew is assumed to be zero from here on
+	Ok(CameraIntrinsics {
+		width: 0,
+		height: 0,
+		fx,
+		fy,
+		cx,
+		cy,
+		distortion: [0.; 5],
+	})
+}
+
+// `h` maps the board plane to normalised image coordinates, i.e. K^-1 H
+fn pose_from_homography(h: &Matrix3<f64>) -> Pose {
+	let (h1, h2, h3) = (h.column(0), h.column(1), h.column(2));
+	let mut scale = 1. / h1.norm();
+	// the board has to be in front of the camera
+	if h3.z * scale < 0. {
+		scale = -scale;
+	}
+	let r1 = h1 * scale;
+	let r2 = h2 * scale;
+	let r3 = r1.cross(&r2);
+	let rotation = Matrix3::from_columns(&[r1.into_owned(), r2.into_owned(), r3]);
+	Pose::new(h3 * scale, Rotation::from_matrix(&rotation))
+}
+
+// Board pose in the camera frame from its detected corners: homography for the initial
+// guess, then refined on the reprojection error.
+pub fn solve_pnp(
+	intrinsics: &CameraIntrinsics,
+	object: &[Point3<f64>],
+	corners: &[Point2<f64>],
+) -> Result<Pose> {
+	let plane: Vec<_> = object.iter().map(|p| Point2::new(p.x, p.y)).collect();
+	let normalised: Vec<_> = corners.iter().map(|&c| intrinsics.undistort(c)).collect();
+	let initial = pose_from_homography(&homography(&plane, &normalised)?);
+
+	let residuals = |p: &DVector<f64>| {
+		let pose = Pose::from_ur([p[0], p[1], p[2], p[3], p[4], p[5]]);
+		let mut r = DVector::zeros(object.len() * 2);
+		for (i, (obj, corner)) in object.iter().zip(corners).enumerate() {
+			let projected = intrinsics.project(&pose.transform_point(obj));
+			r[2 * i] = projected.x - corner.x;
+			r[2 * i + 1] = projected.y - corner.y;
+		}
+		r
+	};
+	let p = lm::minimize(
+		DVector::from_row_slice(&initial.to_ur()),
+		residuals,
+		LmSettings::default(),
+	);
+	Ok(Pose::from_ur([p[0], p[1], p[2], p[3], p[4], p[5]]))
+}
+
+pub struct CheckerboardDetector {
+	pub board: Checkerboard,
+	pub intrinsics: CameraIntrinsics,
+}
+
+impl TargetDetector for CheckerboardDetector {
+	fn detect(&mut self, frame: &[u8]) -> Result<Option<Pose>> {
+		let image = GrayImage::from_jpeg(frame)?;
+		let Some(corners) = find_corners(&image, &self.board) else {
+			return Ok(None);
+		};
+		solve_pnp(&self.intrinsics, &self.board.object_points(), &corners).map(Some)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// 9x6 squares, so the two corner squares differ in colour and the detected order is
+	// unambiguous
+	const BOARD: Checkerboard = Checkerboard {
+		cols: 8,
+		rows: 5,
+		square: 0.025,
+	};
+
+	const TRUTH: CameraIntrinsics = CameraIntrinsics {
+		width: 480,
+		height: 360,
+		fx: 420.,
+		fy: 425.,
+		cx: 236.,
+		cy: 183.,
+		distortion: [-0.12, 0.04, 0.001, -0.0015, 0.],
+	};
+
+	// Undistorted normalised coordinates of every pixel corner, (w + 1) x (h + 1), shared by
+	// every view. Undistorting each sample instead makes rendering far too slow.
+	fn corner_rays(intrinsics: &CameraIntrinsics) -> Vec<Point2<f64>> {
+		let (w, h) = (intrinsics.width as usize, intrinsics.height as usize);
+		(0..=h)
+			.flat_map(|y| {
+				(0..=w)
+					.map(move |x| intrinsics.undistort(Point2::new(x as f64 - 0.5, y as f64 - 0.5)))
+			})
+			.collect()
+	}
+
+	// Ray casts every pixel (4x4 supersampled, interpolating `corner_rays`) onto the board
+	// plane. The board's squares run from -1 to cols/rows in square units around the
+	// corners, with a light margin around them and the square next to the first corner dark.
+	fn render(
+		intrinsics: &CameraIntrinsics,
+		rays: &[Point2<f64>],
+		board_to_camera: &Pose,
+	) -> GrayImage {
+		const SAMPLES: usize = 4;
+		let camera_to_board = board_to_camera.inverse();
+		let origin = camera_to_board.translation;
+		let m = camera_to_board.rotation.matrix();
+		let r: [[f64; 3]; 3] = std::array::from_fn(|i| std::array::from_fn(|j| m[(i, j)]));
+		let (w, h) = (intrinsics.width as usize, intrinsics.height as usize);
+		let mut data = Vec::with_capacity(w * h);
+		for y in 0..h {
+			for x in 0..w {
+				let [a, b, c, d] = [
+					rays[y * (w + 1) + x],
+					rays[y * (w + 1) + x + 1],
+					rays[(y + 1) * (w + 1) + x],
+					rays[(y + 1) * (w + 1) + x + 1],
+				];
+				let mut acc = 0.;
+				for s in 0..SAMPLES * SAMPLES {
+					// plain f64 maths, nalgebra is slow in unoptimised test builds
+					let offset = |i: usize| (i as f64 + 0.5) / SAMPLES as f64;
+					let (fx, fy) = (offset(s % SAMPLES), offset(s / SAMPLES));
+					let lerp = |p: f64, q: f64, f: f64| p + (q - p) * f;
+					let px = lerp(lerp(a.x, b.x, fx), lerp(c.x, d.x, fx), fy);
+					let py = lerp(lerp(a.y, b.y, fx), lerp(c.y, d.y, fx), fy);
+					let dir = [
+						r[0][0] * px + r[0][1] * py + r[0][2],
+						r[1][0] * px + r[1][1] * py + r[1][2],
+						r[2][0] * px + r[2][1] * py + r[2][2],
+					];
+					let t = -origin.z / dir[2];
+					let (bx, by) = (
+						(origin.x + t * dir[0]) / BOARD.square,
+						(origin.y + t * dir[1]) / BOARD.square,
+					);
+					let (i, j) = (bx.floor() as i64 + 1, by.floor() as i64 + 1);
+					let on_board = (0..=BOARD.cols as i64).contains(&i)
+						&& (0..=BOARD.rows as i64).contains(&j);
+					acc += if t > 0. && on_board && (i + j) % 2 == 0 {
+						30.
+					} else {
+						220.
+					};
+				}
+				data.push(acc / (SAMPLES * SAMPLES) as f32);
+			}
+		}
+		GrayImage::new(w, h, data)
+	}
+
+	fn views() -> Vec<Pose> {
+		let centre = Vector3::new(3.5 * BOARD.square, 2. * BOARD.square, 0.);
+		[
+			([0.3, 0., 0.], [0., 0., 0.42]),
+			([-0.3, 0.05, 0.], [0.03, -0.02, 0.45]),
+			([0., 0.35, 0.1], [-0.04, 0.02, 0.44]),
+			([0.05, -0.35, -0.1], [0.04, 0.03, 0.43]),
+			([0.25, 0.25, 0.], [-0.06, -0.05, 0.47]),
+			([-0.2, 0.3, 0.2], [0.06, 0.05, 0.46]),
+			([0.2, -0.25, -0.15], [-0.05, 0.06, 0.45]),
+		]
+		.into_iter()
+		.map(|(rotation, position)| {
+			let rotation = Rotation::from_rotation_vector(rotation);
+			Pose::new(Vector3::from(position) - rotation.apply(&centre), rotation)
+		})
+		.collect()
+	}
+
+	#[test]
+	fn detects_rendered_corners() {
+		let pose = views()[0];
+		let corners = find_corners(&render(&TRUTH, &corner_rays(&TRUTH), &pose), &BOARD)
+			.expect("board not found");
+		for (corner, object) in corners.iter().zip(BOARD.object_points()) {
+			let expected = TRUTH.project(&pose.transform_point(&object));
+			assert!(
+				(corner - expected).norm() < 0.25,
+				"corner at {corner:?}, expected {expected:?}"
+			);
+		}
+	}
+
+	#[test]
+	fn recovers_intrinsics() {
+		let rays = corner_rays(&TRUTH);
+		let images: Vec<_> = views()
+			.iter()
+			.enumerate()
+			.map(|(i, pose)| (format!("{i}"), render(&TRUTH, &rays, pose)))
+			.collect();
+		let calibration = calibrate(&BOARD, &images).unwrap();
+		let found = calibration.intrinsics;
+		assert!(
+			calibration.rejected.is_empty(),
+			"{:?}",
+			calibration.rejected
+		);
+		assert!(calibration.rms < 0.2, "rms {}", calibration.rms);
+		for (name, value, expected, tolerance) in [
+			("fx", found.fx, TRUTH.fx, 2.),
+			("fy", found.fy, TRUTH.fy, 2.),
+			("cx", found.cx, TRUTH.cx, 2.),
+			("cy", found.cy, TRUTH.cy, 2.),
+			("k1", found.distortion[0], TRUTH.distortion[0], 0.02),
+			("k2", found.distortion[1], TRUTH.distortion[1], 0.05),
+			("p1", found.distortion[2], TRUTH.distortion[2], 0.002),
+			("p2", found.distortion[3], TRUTH.distortion[3], 0.002),
+		] {
+			assert!(
+				(value - expected).abs() < tolerance,
+				"{name} is {value}, expected {expected}"
+			);
+		}
+	}
+}
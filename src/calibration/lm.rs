@@ -0,0 +1,81 @@
+use nalgebra::{DMatrix, DVector};
+
+#[derive(Debug, Clone, Copy)]
+pub struct LmSettings {
+	pub max_iterations: usize,
+	// stop once an accepted step improves the cost by less than this fraction
+	pub tolerance: f64,
+}
+
+impl Default for LmSettings {
+	fn default() -> Self {
+		Self {
+			max_iterations: 100,
+			tolerance: 1e-10,
+		}
+	}
+}
+
+// Minimises the sum of squared residuals with Levenberg-Marquardt, using a forward difference
+// Jacobian. Problems here are small enough (a few hundred parameters at most) that a dense
+// Jacobian is fine.
+pub fn minimize<F>(mut params: DVector<f64>, residuals: F, settings: LmSettings) -> DVector<f64>
+where
+	F: Fn(&DVector<f64>) -> DVector<f64>,
+{
+	let mut r = residuals(&params);
+	let mut cost = r.norm_squared();
+	let mut lambda = 1e-3;
+
+	for _ in 0..settings.max_iterations {
+		let jac = jacobian(&params, &r, &residuals);
+		let jtj = jac.transpose() * &jac;
+		let jtr = jac.transpose() * &r;
+
+		let mut improved = false;
+		while lambda < 1e12 {
+			let mut damped = jtj.clone();
+			for i in 0..damped.nrows() {
+				damped[(i, i)] += lambda * jtj[(i, i)].max(1e-12);
+			}
+			let Some(chol) = damped.cholesky() else {
+				lambda *= 10.;
+				continue;
+			};
+			let step = chol.solve(&-&jtr);
+			let candidate = &params + &step;
+			let r_candidate = residuals(&candidate);
+			let cost_candidate = r_candidate.norm_squared();
+			if cost_candidate.is_finite() && cost_candidate < cost {
+				let gain = (cost - cost_candidate) / cost.max(f64::MIN_POSITIVE);
+				params = candidate;
+				r = r_candidate;
+				cost = cost_candidate;
+				lambda = (lambda / 10.).max(1e-12);
+				improved = gain > settings.tolerance;
+				break;
+			}
+			lambda *= 10.;
+		}
+		if !improved {
+			break;
+		}
+	}
+	params
+}
+
+fn jacobian<F>(params: &DVector<f64>, r: &DVector<f64>, residuals: &F) -> DMatrix<f64>
+where
+	F: Fn(&DVector<f64>) -> DVector<f64>,
+{
+	let mut jac = DMatrix::zeros(r.len(), params.len());
+	let mut shifted = params.clone();
+	for j in 0..params.len() {
+		let h = 1e-7 * params[j].abs().max(1.);
+		shifted[j] = params[j] + h;
+		let r_shifted = residuals(&shifted);
+		jac.column_mut(j).copy_from(&((r_shifted - r) / h));
+		shifted[j] = params[j];
+	}
+	jac
+}
@@ -1,34 +1,89 @@
 #![allow(dead_code)]
 
+pub mod controls;
+pub mod convert;
+pub mod device;
+pub mod error;
+pub mod events;
+pub mod exposure;
+pub mod focus;
+pub mod format;
+pub mod frame;
+pub mod group;
+pub mod guard;
 mod internal;
+pub mod profile;
+pub mod replay;
+pub mod selection;
+pub mod source;
+pub mod supervisor;
+pub mod validate;
 
 use std::{
 	io,
 	marker::PhantomData,
 	os::fd::{FromRawFd, OwnedFd},
+	path::Path,
+	sync::Arc,
 };
 
-use tokio::io::unix::AsyncFd;
+use tokio::io::{unix::AsyncFd, Interest};
 
 use nix::{
 	fcntl::{open, OFlag},
 	sys::stat::Mode,
-	NixPath,
 };
 
+pub use error::CameraError;
+pub use frame::FrameInfo;
+pub use internal::{BufferMemory, VideoPixelFormat, MJPEG_FMT};
+
+use controls::{ControlInfo, ControlValue};
+use convert::Yuv444;
+use device::{DeviceInfo, DeviceSelector};
+use events::{CameraEvents, EventKind};
+use format::{fourcc_name, FormatInfo, Fraction, FrameIntervals};
+use frame::{Frame, StreamStats};
+use guard::Frames;
 use internal::{
-	enable_video_stream, get_dev_settings, set_dev_settings, FrameBufferPool, VideoFormat,
-	VideoPixelFormat, BLACKLIGHT_COMPENSATION, BRIGHTNESS, CONTRAST, EXPOSURE, EXPOSURE_AUTO, GAIN,
-	GAMMA, HUE, MJPEG_FMT, SATURATION, WHITE_BALANCE, WHITE_BALANCE_AUTO,
+	get_dev_ext_settings, get_dev_settings, set_dev_ext_settings, set_dev_settings,
+	FrameBufferPool, VideoFormat, BLACKLIGHT_COMPENSATION, BRIGHTNESS, CONTRAST, EXPOSURE,
+	EXPOSURE_AUTO, FOCUS_ABSOLUTE, FOCUS_AUTO, FOCUS_RELATIVE, GAIN, GAMMA, HUE, SATURATION,
+	WHITE_BALANCE, WHITE_BALANCE_AUTO,
 };
+use profile::ProfileStore;
+use selection::{Crop, Rect, SelectionTarget};
+use source::FrameSource;
+use validate::IntegrityStats;
 
-pub struct CameraStream<'cam, 'buf> {
+pub struct CameraStream<'cam> {
 	cam: &'cam mut Camera,
-	buffer: &'buf mut FrameBufferPool,
+	stats: StreamStats,
+}
+
+// Configured: format negotiated, no buffers allocated
+// Ready: buffers allocated and mapped, none of them queued
+// Streaming: STREAMON, buffers cycling between us and the driver
+// Disconnected: the device went away, see `supervisor` for reopening it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamState {
+	Configured,
+	Ready,
+	Streaming,
+	Disconnected,
 }
 
 pub struct Camera {
-	dev: AsyncFd<OwnedFd>,
+	dev: Arc<AsyncFd<OwnedFd>>,
+	info: DeviceInfo,
+	format: VideoPixelFormat,
+	bytes_per_line: u32,
+	num_buffers: u32,
+	memory: BufferMemory,
+	// shared with any `FrameGuard`s still alive
+	buffers: Option<Arc<FrameBufferPool>>,
+	streaming: bool,
+	disconnected: bool,
 }
 
 pub trait CameraSetting {
@@ -36,67 +91,592 @@ pub trait CameraSetting {
 }
 
 impl Camera {
-	pub async fn new<P: NixPath + ?Sized>(path: &P) -> io::Result<(Self, FrameBufferPool)> {
-		let fd = open(path, OFlag::O_RDWR | OFlag::O_NONBLOCK, Mode::empty())?;
-		// SAFETY: the fd was opened right above, returning if it failed, so this should be safe
-		let dev = unsafe { OwnedFd::from_raw_fd(fd) };
-		let dev = AsyncFd::new(dev)?;
-		let num_buffers = 4;
-
-		let fmt = VideoFormat::new()
-			.set_video_capture_type()
-			.set_pix_format(VideoPixelFormat {
+	pub async fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+		Self::with_format(
+			path,
+			VideoPixelFormat {
 				width: 1920,
 				height: 1080,
 				format: MJPEG_FMT,
-			});
-		fmt.apply(&dev);
+			},
+		)
+		.await
+	}
+
+	// Applies the camera's profile called `name` if `profiles` has one, otherwise leaves
+	// its controls as they are
+	pub async fn with_profile(
+		path: impl AsRef<Path>,
+		profiles: &ProfileStore,
+		name: &str,
+	) -> io::Result<Self> {
+		let mut cam = Self::new(path).await?;
+		if let Some(profile) = profiles.get(&cam.info, name) {
+			profile.apply(&mut cam)?;
+		}
+		Ok(cam)
+	}
+
+	// Errors rather than streaming whatever the driver substituted if it doesn't support
+	// `format` exactly, see `formats` for what it does support.
+	pub async fn with_format(path: impl AsRef<Path>, format: VideoPixelFormat) -> io::Result<Self> {
+		Self::open_path(path.as_ref(), format, None).await
+	}
+
+	// Same as `set_frame_interval` straight after opening
+	pub async fn with_frame_interval(
+		path: impl AsRef<Path>,
+		format: VideoPixelFormat,
+		interval: Fraction,
+	) -> io::Result<Self> {
+		Self::open_path(path.as_ref(), format, Some(interval)).await
+	}
+
+	async fn open_path(
+		path: &Path,
+		format: VideoPixelFormat,
+		interval: Option<Fraction>,
+	) -> io::Result<Self> {
+		// PRIORITY is how the driver signals events, see `events`
+		let dev = Arc::new(AsyncFd::with_interest(
+			open_device(path)?,
+			Interest::READABLE | Interest::PRIORITY,
+		)?);
+		let info = DeviceInfo::query(path, &dev)?;
+		if !info.is_capture() {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("{} can't stream video", path.display()),
+			));
+		}
+		let num_buffers = 4;
+
+		let negotiated = negotiate_format(&dev, format)?;
+		let format = negotiated.pix_format();
+		if let Some(interval) = interval {
+			format::set_frame_interval(&dev, format, interval)?;
+		}
+
+		let memory = BufferMemory::default();
+		let buffers = FrameBufferPool::new(&dev, num_buffers, memory)?;
+
+		Ok(Self {
+			dev,
+			info,
+			format,
+			bytes_per_line: negotiated.bytes_per_line(),
+			num_buffers,
+			memory,
+			buffers: Some(Arc::new(buffers)),
+			streaming: false,
+			disconnected: false,
+		})
+	}
+
+	// By serial or bus path, which stay the same across reboots unlike /dev/videoN
+	pub async fn open(device: &DeviceSelector, format: VideoPixelFormat) -> io::Result<Self> {
+		Self::with_format(device.resolve()?, format).await
+	}
+
+	pub fn info(&self) -> &DeviceInfo {
+		&self.info
+	}
+
+	pub fn frame_interval(&self) -> io::Result<Fraction> {
+		format::frame_interval(&self.dev)
+	}
+
+	// Most drivers (uvcvideo included) return EBUSY while streaming, so this stops the
+	// stream first if need be. It's left stopped, `stream` starts it again.
+	pub fn set_frame_interval(&mut self, interval: Fraction) -> io::Result<Fraction> {
+		self.stop()?;
+		format::set_frame_interval(&self.dev, self.format, interval)
+	}
+
+	// for the negotiated format and size
+	pub fn frame_intervals(&self) -> io::Result<FrameIntervals> {
+		format::enumerate_intervals(
+			&self.dev,
+			self.format.format,
+			self.format.width,
+			self.format.height,
+		)
+	}
+
+	pub fn formats(&self) -> io::Result<Vec<FormatInfo>> {
+		format::enumerate_formats(&self.dev)
+	}
 
-		let buffers = FrameBufferPool::new(&dev, num_buffers)?;
+	// Without opening (and so negotiating a format for) the camera first
+	pub fn probe_formats(path: impl AsRef<Path>) -> io::Result<Vec<FormatInfo>> {
+		format::enumerate_formats(&open_device(path)?)
+	}
+
+	pub fn controls(&self) -> io::Result<Vec<ControlInfo>> {
+		controls::enumerate_controls(&self.dev)
+	}
+
+	// Control changes made by other processes or the camera, source changes and end of
+	// stream. Only borrows the camera to subscribe, so frames can be read while waiting.
+	pub fn events(&self, kinds: &[EventKind]) -> io::Result<CameraEvents> {
+		let controls = if kinds.contains(&EventKind::Control) {
+			self.controls()?.iter().map(|c| c.id).collect()
+		} else {
+			Vec::new()
+		};
+		CameraEvents::subscribe(self.dev.clone(), self.info.path.clone(), kinds, &controls)
+	}
+
+	pub fn probe_controls(path: impl AsRef<Path>) -> io::Result<Vec<ControlInfo>> {
+		controls::enumerate_controls(&open_device(path)?)
+	}
+
+	pub fn control<T: CameraSetting>(&self) -> io::Result<ControlInfo> {
+		controls::query_control(&self.dev, T::ID)
+	}
+
+	// as negotiated with the driver
+	pub fn format(&self) -> VideoPixelFormat {
+		self.format
+	}
+
+	// Row stride of uncompressed frames, see `convert::Yuv444::convert`
+	pub fn bytes_per_line(&self) -> u32 {
+		self.bytes_per_line
+	}
+
+	pub fn state(&self) -> StreamState {
+		if self.disconnected {
+			StreamState::Disconnected
+		} else if self.streaming {
+			StreamState::Streaming
+		} else if self.buffers.is_some() {
+			StreamState::Ready
+		} else {
+			StreamState::Configured
+		}
+	}
 
-		enable_video_stream(&dev);
+	// Buffers actually allocated, which can differ from what was asked for
+	pub fn num_buffers(&self) -> usize {
+		self.buffers.as_ref().map_or(0, |buffers| buffers.len())
+	}
+
+	// A copy of the next frame. Leaves the stream running so grabbing another is quick, the
+	// frames in between are dropped by the driver once every buffer is full.
+	pub async fn capture_frame(&mut self) -> Result<Vec<u8>, CameraError> {
+		self.start()?;
+		self.next_frame(|data, _| data.to_vec()).await
+	}
+
+	// Starts streaming if it isn't already, stats cover just this `CameraStream`
+	pub fn stream(&mut self) -> io::Result<CameraStream<'_>> {
+		self.start()?;
+		Ok(CameraStream {
+			cam: self,
+			stats: StreamStats::default(),
+		})
+	}
+
+	// Owned frames that requeue their buffer when dropped, so they can be sent to other
+	// tasks. As many can be alive at once as there are buffers, the stream waits for one to
+	// be dropped after that.
+	pub fn frames(&mut self) -> io::Result<Frames<'_>> {
+		self.start()?;
+		Ok(Frames::new(self))
+	}
+
+	pub fn start(&mut self) -> io::Result<()> {
+		if self.streaming {
+			return Ok(());
+		}
+		let buffers = match &self.buffers {
+			Some(buffers) => buffers,
+			None => self.buffers.insert(Arc::new(FrameBufferPool::new(
+				&self.dev,
+				self.num_buffers,
+				self.memory,
+			)?)),
+		};
+		buffers.start()?;
+		self.streaming = true;
+		Ok(())
+	}
+
+	// Buffers stay allocated, so starting again doesn't need to map them again. Frames
+	// still held keep their data until they're dropped.
+	pub fn stop(&mut self) -> io::Result<()> {
+		if !self.streaming {
+			return Ok(());
+		}
+		if let Some(buffers) = &self.buffers {
+			buffers.stop()?;
+		}
+		self.streaming = false;
+		Ok(())
+	}
+
+	// Changing the format changes the buffer size, so the old buffers are freed and new ones
+	// allocated. Stops the stream, which stays stopped after.
+	pub fn reconfigure(&mut self, format: VideoPixelFormat, num_buffers: u32) -> io::Result<()> {
+		self.stop()?;
+		self.release_buffers()?;
+		let negotiated = negotiate_format(&self.dev, format)?;
+		self.format = negotiated.pix_format();
+		self.bytes_per_line = negotiated.bytes_per_line();
+		self.num_buffers = num_buffers;
+		self.buffers = Some(Arc::new(FrameBufferPool::new(
+			&self.dev,
+			num_buffers,
+			self.memory,
+		)?));
+		Ok(())
+	}
+
+	// Switching reallocates the buffers, so like `reconfigure` it stops the stream
+	pub fn set_buffer_memory(&mut self, memory: BufferMemory) -> io::Result<()> {
+		self.stop()?;
+		self.release_buffers()?;
+		self.memory = memory;
+		self.buffers = Some(Arc::new(FrameBufferPool::new(
+			&self.dev,
+			self.num_buffers,
+			memory,
+		)?));
+		Ok(())
+	}
 
-		Ok((Self { dev }, buffers))
+	pub fn buffer_memory(&self) -> BufferMemory {
+		self.memory
 	}
 
-	pub async fn capture_frame<'fb>(
+	// dmabuf fds for every buffer, in buffer index order (see `FrameInfo::index`), so other
+	// consumers (GPU, encoder) can import the frames without copying them. They're only
+	// worth re-reading after `with_frame` hands out that index, the driver reuses them.
+	pub fn export_buffers(&self) -> io::Result<Vec<OwnedFd>> {
+		let buffers = self
+			.buffers
+			.as_ref()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No buffers allocated"))?;
+		(0..buffers.len()).map(|idx| buffers.export(idx)).collect()
+	}
+
+	pub fn selection(&self, target: SelectionTarget) -> io::Result<Rect> {
+		selection::selection(&self.dev, target)
+	}
+
+	// Cropping usually changes the frame size, so like `reconfigure` this stops the stream
+	// and reallocates the buffers, then reads back the format the driver ended up with.
+	// Returns the rectangle the driver used, which can be rounded from `rect`.
+	pub fn set_selection(&mut self, target: SelectionTarget, rect: Rect) -> io::Result<Rect> {
+		self.stop()?;
+		self.release_buffers()?;
+		let res = selection::set_selection(&self.dev, target, rect);
+		// the buffers are needed again whether it worked or not
+		let negotiated = VideoFormat::read(&self.dev)?;
+		self.format = negotiated.pix_format();
+		self.bytes_per_line = negotiated.bytes_per_line();
+		self.buffers = Some(Arc::new(FrameBufferPool::new(
+			&self.dev,
+			self.num_buffers,
+			self.memory,
+		)?));
+		res
+	}
+
+	// Crops on the sensor if the driver can do exactly `rect` at 1:1, otherwise puts the
+	// crop back and leaves it to be done in software. `rect` is in the current frame's
+	// pixels.
+	pub fn crop(&mut self, rect: Rect) -> io::Result<Crop> {
+		if rect.is_empty() {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("Can't crop to an empty {rect:?}"),
+			));
+		}
+		if !rect.fits_in(self.format.width, self.format.height) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!(
+					"{rect:?} doesn't fit in a {}x{} frame",
+					self.format.width, self.format.height
+				),
+			));
+		}
+		let applied = match self.set_selection(SelectionTarget::Crop, rect) {
+			Ok(applied) => applied,
+			Err(e) if selection::is_unsupported(&e) => return Ok(Crop::Software(rect)),
+			Err(e) => return Err(e),
+		};
+		if applied == rect && (self.format.width, self.format.height) == (rect.width, rect.height) {
+			return Ok(Crop::Sensor(rect));
+		}
+		// rounded, or scaled back up to the old size
+		let default = self.selection(SelectionTarget::CropDefault)?;
+		self.set_selection(SelectionTarget::Crop, default)?;
+		Ok(Crop::Software(rect))
+	}
+
+	// Fails with the buffers left in place while any `FrameGuard` still holds a frame
+	fn release_buffers(&mut self) -> io::Result<()> {
+		let Some(buffers) = self.buffers.take() else {
+			return Ok(());
+		};
+		match Arc::try_unwrap(buffers) {
+			// nothing left to free them on, unmapping is all that's needed
+			Ok(buffers) if self.disconnected => {
+				drop(buffers);
+				Ok(())
+			}
+			Ok(buffers) => buffers.release(),
+			Err(buffers) => {
+				let held = Arc::strong_count(&buffers) - 1;
+				self.buffers = Some(buffers);
+				Err(io::Error::new(
+					io::ErrorKind::ResourceBusy,
+					format!("{held} frames are still held"),
+				))
+			}
+		}
+	}
+
+	// A frame that was dequeued before the device went away is still handed to `func`, the
+	// disconnect is reported on the next call instead.
+	async fn next_frame<F, R>(&mut self, func: F) -> Result<R, CameraError>
+	where
+		F: FnOnce(&[u8], FrameInfo) -> R,
+	{
+		if self.disconnected {
+			return Err(CameraError::Disconnected(self.info.path.clone()));
+		}
+		let buffers = self
+			.buffers
+			.clone()
+			.expect("buffers are allocated while streaming");
+		let (index, info) = match buffers.dequeue().await {
+			Ok(dequeued) => dequeued,
+			Err(e) => return Err(self.device_error(e)),
+		};
+		let res = func(buffers.data(index, info.bytesused as usize), info);
+		if let Err(e) = buffers.requeue(index) {
+			let err = self.device_error(e);
+			if !err.is_disconnected() {
+				return Err(err);
+			}
+		}
+		Ok(res)
+	}
+
+	// Decodes a frame captured after every one already queued plus `settle` more, so it
+	// shows the effect of controls set just before. Starts the stream if needed.
+	async fn settled_frame(
 		&mut self,
-		buffer: &'fb mut FrameBufferPool,
-	) -> io::Result<&'fb [u8]> {
-		buffer.capture(&mut self.dev).await
+		planes: &mut Yuv444,
+		settle: usize,
+	) -> Result<(), CameraError> {
+		if let Err(e) = self.start() {
+			return Err(self.device_error(e));
+		}
+		let format = self.format;
+		let stride = self.bytes_per_line;
+		for _ in 0..self.num_buffers() + settle {
+			self.next_frame(|_, _| ()).await?;
+		}
+		self.next_frame(|data, _| planes.convert(format, stride, data))
+			.await??;
+		Ok(())
 	}
 
-	pub fn stream<'cam, 'buf>(
-		&'cam mut self,
-		buffer: &'buf mut FrameBufferPool,
-	) -> io::Result<CameraStream<'cam, 'buf>> {
-		buffer.enqueue_all(&mut self.dev)?;
-		Ok(CameraStream { cam: self, buffer })
+	// The buffers can't be queued or freed once the device is gone, so stop trying
+	fn device_error(&mut self, err: io::Error) -> CameraError {
+		let err = CameraError::from_io(err, &self.info.path);
+		if err.is_disconnected() {
+			self.disconnected = true;
+			self.streaming = false;
+		}
+		err
 	}
 
-	pub fn set<T: CameraSetting>(&mut self, value: i32) {
-		set_dev_settings(&self.dev, T::ID, value);
+	// Clamps `value` to the control's range (or nearest menu entry), returning what was
+	// actually set.
+	pub fn set<T: CameraSetting>(&mut self, value: i32) -> io::Result<i32> {
+		let value = self.checked_value(T::ID, value)?;
+		set_dev_settings(&self.dev, T::ID, value)?;
+		Ok(value)
 	}
 
-	pub fn get<T: CameraSetting>(&self) -> i32 {
+	pub fn get<T: CameraSetting>(&self) -> io::Result<i32> {
 		get_dev_settings(&self.dev, T::ID)
 	}
+
+	// Applied together in one VIDIOC_S_EXT_CTRLS, so either all of them change or none
+	// do, e.g. exposure, gain and white balance for the same frame.
+	pub fn set_many(&mut self, values: &[ControlValue]) -> io::Result<Vec<i32>> {
+		let values = values
+			.iter()
+			.map(|v| Ok((v.id, self.checked_value(v.id, v.value)?)))
+			.collect::<io::Result<Vec<_>>>()?;
+		set_dev_ext_settings(&self.dev, &values)?;
+		Ok(values.into_iter().map(|(_, value)| value).collect())
+	}
+
+	pub fn get_many(&self, ids: &[u32]) -> io::Result<Vec<i32>> {
+		get_dev_ext_settings(&self.dev, ids)
+	}
+
+	fn checked_value(&self, id: u32, value: i32) -> io::Result<i32> {
+		let info = controls::query_control(&self.dev, id)?;
+		if info.is_read_only() {
+			return Err(io::Error::new(
+				io::ErrorKind::PermissionDenied,
+				format!("{} is read-only", info.name),
+			));
+		}
+		let clamped = info.clamp(value);
+		if clamped != value {
+			eprintln!("{} doesn't accept {value}, setting {clamped}", info.name);
+		}
+		Ok(clamped)
+	}
+}
+
+impl Drop for Camera {
+	fn drop(&mut self) {
+		// Frames still held keep the buffers, and the device, open until the last one is
+		// dropped, closing the device frees the buffers then.
+		let held = self
+			.buffers
+			.as_ref()
+			.is_some_and(|buffers| Arc::strong_count(buffers) > 1);
+		let res = self
+			.stop()
+			.and_then(|_| if held { Ok(()) } else { self.release_buffers() });
+		if let Err(e) = res {
+			eprintln!("Failed releasing {}: {e}", self.info.path.display());
+		}
+	}
 }
 
-impl<'cam, 'buf> CameraStream<'cam, 'buf> {
-	pub async fn with_frame<F, R>(&mut self, func: F) -> io::Result<R>
+impl CameraStream<'_> {
+	pub async fn with_frame<F, R>(&mut self, func: F) -> Result<R, CameraError>
 	where
-		F: FnOnce(&[u8]) -> R,
+		F: FnOnce(Frame) -> R,
 	{
-		let (frame, index) = self.buffer.dequeue(&mut self.cam.dev).await?;
-		let res = func(frame);
-		self.buffer.enqueue(index, &mut self.cam.dev)?;
-		Ok(res)
+		let stats = &mut self.stats;
+		self.cam
+			.next_frame(|data, info| {
+				let dropped = stats.record(&info);
+				func(Frame {
+					data,
+					info,
+					dropped,
+				})
+			})
+			.await
+	}
+
+	pub fn stats(&self) -> StreamStats {
+		self.stats
 	}
 
-	pub async fn stop(self) -> io::Result<()> {
-		self.buffer.dequeue_all(&mut self.cam.dev).await
+	// STREAMOFF, which hands every buffer back without waiting for them to be filled. The
+	// camera can be reconfigured or streamed again after.
+	pub fn stop(self) -> io::Result<()> {
+		self.cam.stop()
+	}
+}
+
+impl FrameSource for Camera {
+	fn format(&self) -> VideoPixelFormat {
+		Camera::format(self)
+	}
+
+	fn bytes_per_line(&self) -> u32 {
+		Camera::bytes_per_line(self)
+	}
+
+	fn frame_interval(&self) -> io::Result<Fraction> {
+		Camera::frame_interval(self)
+	}
+
+	async fn read_frame<F, R>(&mut self, func: F) -> Result<R, CameraError>
+	where
+		F: FnOnce(&[u8], FrameInfo) -> R,
+	{
+		if let Err(e) = self.start() {
+			return Err(self.device_error(e));
+		}
+		self.next_frame(func).await
+	}
+}
+
+// Counted in the stream's stats like `with_frame`
+impl FrameSource for CameraStream<'_> {
+	fn format(&self) -> VideoPixelFormat {
+		self.cam.format()
+	}
+
+	fn bytes_per_line(&self) -> u32 {
+		self.cam.bytes_per_line()
+	}
+
+	fn frame_interval(&self) -> io::Result<Fraction> {
+		self.cam.frame_interval()
+	}
+
+	async fn read_frame<F, R>(&mut self, func: F) -> Result<R, CameraError>
+	where
+		F: FnOnce(&[u8], FrameInfo) -> R,
+	{
+		let stats = &mut self.stats;
+		self.cam
+			.next_frame(|data, info| {
+				stats.record(&info);
+				func(data, info)
+			})
+			.await
+	}
+
+	fn integrity(&mut self) -> Option<&mut IntegrityStats> {
+		Some(&mut self.stats.integrity)
+	}
+}
+
+fn open_device(path: impl AsRef<Path>) -> io::Result<OwnedFd> {
+	let fd = open(
+		path.as_ref(),
+		OFlag::O_RDWR | OFlag::O_NONBLOCK,
+		Mode::empty(),
+	)?;
+	// SAFETY: the fd was opened right above, returning if it failed, so this should be safe
+	Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn negotiate_format(
+	dev: &AsyncFd<OwnedFd>,
+	requested: VideoPixelFormat,
+) -> io::Result<VideoFormat> {
+	VideoFormat::new()
+		.set_video_capture_type()
+		.set_pix_format(requested)
+		.apply(dev)?;
+	let negotiated = VideoFormat::read(dev)?;
+	let actual = negotiated.pix_format();
+	if actual != requested {
+		return Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			format!(
+				"Requested {}x{} {} but the driver set {}x{} {}",
+				requested.width,
+				requested.height,
+				fourcc_name(requested.format),
+				actual.width,
+				actual.height,
+				fourcc_name(actual.format),
+			),
+		));
 	}
+	Ok(negotiated)
 }
 
 struct View<'a, T> {
@@ -108,7 +688,7 @@ impl<T> View<'_, T>
 where
 	T: CameraSetting,
 {
-	pub fn get(&self) -> i32 {
+	pub fn get(&self) -> io::Result<i32> {
 		self.cam.get::<T>()
 	}
 }
@@ -122,11 +702,11 @@ impl<T> ViewMut<'_, T>
 where
 	T: CameraSetting,
 {
-	pub fn get(&self) -> i32 {
+	pub fn get(&self) -> io::Result<i32> {
 		self.cam.get::<T>()
 	}
 
-	pub fn set(&mut self, value: i32) {
+	pub fn set(&mut self, value: i32) -> io::Result<i32> {
 		self.cam.set::<T>(value)
 	}
 }
@@ -155,4 +735,7 @@ cam_setting! {
 	WhiteBalance => WHITE_BALANCE,
 	BlacklightCompensation => BLACKLIGHT_COMPENSATION,
 	WhiteBalanceAuto => WHITE_BALANCE_AUTO,
+	FocusAbsolute => FOCUS_ABSOLUTE,
+	FocusRelative => FOCUS_RELATIVE,
+	FocusAuto => FOCUS_AUTO,
 }
@@ -0,0 +1,207 @@
+use std::{io, mem, os::fd::AsRawFd};
+
+use nix::{errno::Errno, ioctl_readwrite_bad};
+use v4l2_sys::{
+	v4l2_ctrl_type_V4L2_CTRL_TYPE_BITMASK, v4l2_ctrl_type_V4L2_CTRL_TYPE_BOOLEAN,
+	v4l2_ctrl_type_V4L2_CTRL_TYPE_BUTTON, v4l2_ctrl_type_V4L2_CTRL_TYPE_CTRL_CLASS,
+	v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER, v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER64,
+	v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER_MENU, v4l2_ctrl_type_V4L2_CTRL_TYPE_MENU,
+	v4l2_ctrl_type_V4L2_CTRL_TYPE_STRING, v4l2_queryctrl, v4l2_querymenu, V4L2_CTRL_FLAG_DISABLED,
+	V4L2_CTRL_FLAG_INACTIVE, V4L2_CTRL_FLAG_NEXT_CTRL, V4L2_CTRL_FLAG_READ_ONLY, VIDIOC_QUERYCTRL,
+	VIDIOC_QUERYMENU,
+};
+
+use super::{format::c_str, CameraSetting};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlValue {
+	pub id: u32,
+	pub value: i32,
+}
+
+impl ControlValue {
+	pub fn of<T: CameraSetting>(value: i32) -> Self {
+		Self { id: T::ID, value }
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlType {
+	Integer,
+	Boolean,
+	Menu,
+	IntegerMenu,
+	Button,
+	Integer64,
+	String,
+	Bitmask,
+	// heading for the controls after it, not a control itself
+	Class,
+	Other(u32),
+}
+
+impl From<u32> for ControlType {
+	#[allow(non_upper_case_globals)]
+	fn from(ty: u32) -> Self {
+		match ty {
+			v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER => Self::Integer,
+			v4l2_ctrl_type_V4L2_CTRL_TYPE_BOOLEAN => Self::Boolean,
+			v4l2_ctrl_type_V4L2_CTRL_TYPE_MENU => Self::Menu,
+			v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER_MENU => Self::IntegerMenu,
+			v4l2_ctrl_type_V4L2_CTRL_TYPE_BUTTON => Self::Button,
+			v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER64 => Self::Integer64,
+			v4l2_ctrl_type_V4L2_CTRL_TYPE_STRING => Self::String,
+			v4l2_ctrl_type_V4L2_CTRL_TYPE_BITMASK => Self::Bitmask,
+			v4l2_ctrl_type_V4L2_CTRL_TYPE_CTRL_CLASS => Self::Class,
+			other => Self::Other(other),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MenuEntry {
+	pub index: u32,
+	// integer menus have a value instead of a name, it's formatted into `name` for them
+	pub name: String,
+	pub value: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlInfo {
+	pub id: u32,
+	pub name: String,
+	pub kind: ControlType,
+	pub minimum: i32,
+	pub maximum: i32,
+	pub step: i32,
+	pub default: i32,
+	pub flags: u32,
+	pub menu: Vec<MenuEntry>,
+}
+
+impl ControlInfo {
+	pub fn is_read_only(&self) -> bool {
+		self.flags & V4L2_CTRL_FLAG_READ_ONLY != 0
+	}
+
+	// e.g. manual exposure while auto exposure is on, setting it does nothing (or fails)
+	pub fn is_inactive(&self) -> bool {
+		self.flags & V4L2_CTRL_FLAG_INACTIVE != 0
+	}
+
+	pub fn is_valid(&self, value: i32) -> bool {
+		self.clamp(value) == value
+	}
+
+	// Nearest value the control accepts: inside min..=max, on a step, and for menus one of
+	// the indices that actually has an entry (they can have gaps).
+	pub fn clamp(&self, value: i32) -> i32 {
+		match self.kind {
+			ControlType::Menu | ControlType::IntegerMenu if !self.menu.is_empty() => self
+				.menu
+				.iter()
+				.map(|entry| entry.index as i32)
+				.min_by_key(|&index| (index as i64 - value as i64).abs())
+				.expect("menu isn't empty"),
+			_ => {
+				let value = value.clamp(self.minimum, self.maximum);
+				let step = self.step.max(1) as i64;
+				let offset = value as i64 - self.minimum as i64;
+				let snapped = self.minimum as i64 + (offset + step / 2) / step * step;
+				snapped.min(self.maximum as i64) as i32
+			}
+		}
+	}
+}
+
+pub fn enumerate_controls(dev: &impl AsRawFd) -> io::Result<Vec<ControlInfo>> {
+	let mut controls = Vec::new();
+	let mut id = 0;
+	loop {
+		let mut query: v4l2_queryctrl = unsafe { mem::zeroed() };
+		query.id = id | V4L2_CTRL_FLAG_NEXT_CTRL;
+		match unsafe { v4l2_query_ctrl(dev.as_raw_fd(), &mut query) } {
+			Ok(_) => {}
+			// no controls after `id`
+			Err(Errno::EINVAL) => break,
+			Err(e) => return Err(e.into()),
+		}
+		id = query.id;
+		if query.flags & V4L2_CTRL_FLAG_DISABLED != 0
+			|| ControlType::from(query.type_) == ControlType::Class
+		{
+			continue;
+		}
+		controls.push(control_info(dev, &query)?);
+	}
+	Ok(controls)
+}
+
+pub fn query_control(dev: &impl AsRawFd, id: u32) -> io::Result<ControlInfo> {
+	let mut query: v4l2_queryctrl = unsafe { mem::zeroed() };
+	query.id = id;
+	unsafe { v4l2_query_ctrl(dev.as_raw_fd(), &mut query)? };
+	control_info(dev, &query)
+}
+
+fn control_info(dev: &impl AsRawFd, query: &v4l2_queryctrl) -> io::Result<ControlInfo> {
+	let kind = ControlType::from(query.type_);
+	let menu = match kind {
+		ControlType::Menu | ControlType::IntegerMenu => {
+			query_menu(dev, query.id, query.minimum, query.maximum, kind)?
+		}
+		_ => Vec::new(),
+	};
+	Ok(ControlInfo {
+		id: query.id,
+		name: c_str(&query.name),
+		kind,
+		minimum: query.minimum,
+		maximum: query.maximum,
+		step: query.step,
+		default: query.default_value,
+		flags: query.flags,
+		menu,
+	})
+}
+
+fn query_menu(
+	dev: &impl AsRawFd,
+	id: u32,
+	minimum: i32,
+	maximum: i32,
+	kind: ControlType,
+) -> io::Result<Vec<MenuEntry>> {
+	let mut entries = Vec::new();
+	for index in minimum.max(0) as u32..=maximum.max(0) as u32 {
+		let mut menu: v4l2_querymenu = unsafe { mem::zeroed() };
+		menu.id = id;
+		menu.index = index;
+		match unsafe { v4l2_query_menu(dev.as_raw_fd(), &mut menu) } {
+			Ok(_) => {}
+			// indices in the range can be skipped
+			Err(Errno::EINVAL) => continue,
+			Err(e) => return Err(e.into()),
+		}
+		let entry = if kind == ControlType::IntegerMenu {
+			let value = unsafe { menu.__bindgen_anon_1.value };
+			MenuEntry {
+				index,
+				name: value.to_string(),
+				value: Some(value),
+			}
+		} else {
+			let name = unsafe { menu.__bindgen_anon_1.name };
+			MenuEntry {
+				index,
+				name: c_str(&name),
+				value: None,
+			}
+		};
+		entries.push(entry);
+	}
+	Ok(entries)
+}
+
+ioctl_readwrite_bad!(v4l2_query_ctrl, VIDIOC_QUERYCTRL, v4l2_queryctrl);
+ioctl_readwrite_bad!(v4l2_query_menu, VIDIOC_QUERYMENU, v4l2_querymenu);
@@ -0,0 +1,230 @@
+use std::io;
+
+use zune_jpeg::{
+	zune_core::{colorspace::ColorSpace, options::DecoderOptions},
+	JpegDecoder,
+};
+
+use super::{
+	format::{fourcc_name, GREY_FMT, NV12_FMT, UYVY_FMT, Y16_FMT, YUV444M_FMT, YUYV_FMT},
+	selection::Rect,
+	VideoPixelFormat, MJPEG_FMT,
+};
+
+// Planar YUV 4:4:4, the layout `video::Encoder::encode` takes. Chroma from the subsampled
+// formats is repeated rather than interpolated, and greyscale gets neutral chroma.
+#[derive(Debug, Clone)]
+pub struct Yuv444 {
+	pub width: usize,
+	pub height: usize,
+	pub y: Vec<u8>,
+	pub cb: Vec<u8>,
+	pub cr: Vec<u8>,
+	// interleaved output of the JPEG decoder
+	scratch: Vec<u8>,
+}
+
+impl Yuv444 {
+	pub fn new(width: u32, height: u32) -> Self {
+		let (width, height) = (width as usize, height as usize);
+		Self {
+			width,
+			height,
+			y: vec![0; width * height],
+			cb: vec![128; width * height],
+			cr: vec![128; width * height],
+			scratch: Vec::new(),
+		}
+	}
+
+	pub fn is_supported(fourcc: u32) -> bool {
+		[
+			MJPEG_FMT,
+			YUYV_FMT,
+			UYVY_FMT,
+			NV12_FMT,
+			GREY_FMT,
+			Y16_FMT,
+			YUV444M_FMT,
+		]
+		.contains(&fourcc)
+	}
+
+	// `stride` is the driver's bytesperline (`Camera::bytes_per_line`), rows can be padded
+	// past the visible width. 0 means unpadded, which is what compressed formats report.
+	pub fn convert(
+		&mut self,
+		format: VideoPixelFormat,
+		stride: u32,
+		data: &[u8],
+	) -> io::Result<()> {
+		if (format.width as usize, format.height as usize) != (self.width, self.height) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!(
+					"Converting {}x{} frames into {}x{} planes",
+					format.width, format.height, self.width, self.height
+				),
+			));
+		}
+		let stride = stride as usize;
+		match format.format {
+			MJPEG_FMT => self.decode_jpeg(data),
+			// Y0 U Y1 V
+			YUYV_FMT => self.packed_422(data, stride, [0, 1, 2, 3]),
+			// U Y0 V Y1
+			UYVY_FMT => self.packed_422(data, stride, [1, 0, 3, 2]),
+			NV12_FMT => self.nv12(data, stride),
+			GREY_FMT => self.grey(data, stride, 1),
+			Y16_FMT => self.grey(data, stride, 2),
+			YUV444M_FMT => self.planar_444(data, stride),
+			other => Err(io::Error::new(
+				io::ErrorKind::Unsupported,
+				format!("Can't convert {} frames", fourcc_name(other)),
+			)),
+		}
+	}
+
+	// Copies `rect` out of these planes into `out`, which has to be `rect`'s size. For when
+	// the camera can't crop, see `Camera::crop`.
+	pub fn crop(&self, rect: Rect, out: &mut Yuv444) -> io::Result<()> {
+		if rect.is_empty() {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("Can't crop to an empty {rect:?}"),
+			));
+		}
+		if !rect.fits_in(self.width as u32, self.height as u32)
+			|| (out.width, out.height) != (rect.width as usize, rect.height as usize)
+		{
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!(
+					"Cropping {rect:?} from {}x{} into {}x{}",
+					self.width, self.height, out.width, out.height
+				),
+			));
+		}
+		let (left, top, width) = (rect.left as usize, rect.top as usize, out.width);
+		for (src, dst) in [
+			(&self.y, &mut out.y),
+			(&self.cb, &mut out.cb),
+			(&self.cr, &mut out.cr),
+		] {
+			for row in 0..out.height {
+				dst[row * width..][..width]
+					.copy_from_slice(&src[(top + row) * self.width + left..][..width]);
+			}
+		}
+		Ok(())
+	}
+
+	fn decode_jpeg(&mut self, data: &[u8]) -> io::Result<()> {
+		let mut decoder = JpegDecoder::new_with_options(
+			data,
+			DecoderOptions::new_cmd().jpeg_set_out_colorspace(ColorSpace::YCbCr),
+		);
+		self.scratch.resize(self.width * self.height * 3, 0);
+		decoder
+			.decode_into(&mut self.scratch)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")))?;
+		for (i, px) in self.scratch.chunks_exact(3).enumerate() {
+			self.y[i] = px[0];
+			self.cb[i] = px[1];
+			self.cr[i] = px[2];
+		}
+		Ok(())
+	}
+
+	// `order` is where Y0, U, Y1 and V sit in each 4 byte pair of pixels
+	fn packed_422(&mut self, data: &[u8], stride: usize, order: [usize; 4]) -> io::Result<()> {
+		let row_len = self.width * 2;
+		let stride = check_len(data, stride, row_len, self.height)?;
+		for row in 0..self.height {
+			let line = &data[row * stride..][..row_len];
+			let out = row * self.width;
+			for (pair, px) in line.chunks_exact(4).enumerate() {
+				let x = out + pair * 2;
+				self.y[x] = px[order[0]];
+				self.y[x + 1] = px[order[2]];
+				self.cb[x] = px[order[1]];
+				self.cb[x + 1] = px[order[1]];
+				self.cr[x] = px[order[3]];
+				self.cr[x + 1] = px[order[3]];
+			}
+		}
+		Ok(())
+	}
+
+	// Full resolution Y plane followed by a half resolution interleaved UV plane, both with
+	// the same stride
+	fn nv12(&mut self, data: &[u8], stride: usize) -> io::Result<()> {
+		let chroma_rows = self.height.div_ceil(2);
+		let chroma_len = self.width.div_ceil(2) * 2;
+		let stride = check_len(data, stride, chroma_len, self.height + chroma_rows)?;
+		let (luma, chroma) = data.split_at(stride * self.height);
+		for row in 0..self.height {
+			let out = row * self.width;
+			self.y[out..][..self.width].copy_from_slice(&luma[row * stride..][..self.width]);
+			let uv = &chroma[row / 2 * stride..][..chroma_len];
+			for x in 0..self.width {
+				self.cb[out + x] = uv[x / 2 * 2];
+				self.cr[out + x] = uv[x / 2 * 2 + 1];
+			}
+		}
+		Ok(())
+	}
+
+	// Each plane is `height` rows of `stride`
+	fn planar_444(&mut self, data: &[u8], stride: usize) -> io::Result<()> {
+		let stride = check_len(data, stride, self.width, self.height * 3)?;
+		let plane_len = stride * self.height;
+		for (plane, out) in [&mut self.y, &mut self.cb, &mut self.cr]
+			.into_iter()
+			.enumerate()
+		{
+			let src = &data[plane * plane_len..];
+			for row in 0..self.height {
+				out[row * self.width..][..self.width]
+					.copy_from_slice(&src[row * stride..][..self.width]);
+			}
+		}
+		Ok(())
+	}
+
+	// Y16 is little endian, its high byte is the 8 bit value
+	fn grey(&mut self, data: &[u8], stride: usize, bytes: usize) -> io::Result<()> {
+		let row_len = self.width * bytes;
+		let stride = check_len(data, stride, row_len, self.height)?;
+		for row in 0..self.height {
+			let line = &data[row * stride..][..row_len];
+			let out = &mut self.y[row * self.width..][..self.width];
+			for (y, px) in out.iter_mut().zip(line.chunks_exact(bytes)) {
+				*y = px[bytes - 1];
+			}
+		}
+		self.cb.fill(128);
+		self.cr.fill(128);
+		Ok(())
+	}
+}
+
+// Returns the stride to use, making sure `rows` rows of `row_len` bytes fit. The last row
+// doesn't need its padding.
+fn check_len(data: &[u8], stride: usize, row_len: usize, rows: usize) -> io::Result<usize> {
+	let stride = if stride == 0 { row_len } else { stride };
+	if stride < row_len {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("Stride of {stride} bytes is shorter than a {row_len} byte row"),
+		));
+	}
+	let needed = stride * rows.saturating_sub(1) + row_len;
+	if data.len() < needed {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("Frame is {} bytes but needs at least {needed}", data.len()),
+		));
+	}
+	Ok(stride)
+}
@@ -0,0 +1,157 @@
+use std::{
+	fs, io, mem,
+	os::fd::{AsFd, AsRawFd},
+	path::{Path, PathBuf},
+	str::FromStr,
+};
+
+use nix::{
+	ioctl_read_bad,
+	sys::stat::{fstat, major, minor},
+};
+use v4l2_sys::{
+	v4l2_capability, V4L2_CAP_DEVICE_CAPS, V4L2_CAP_STREAMING, V4L2_CAP_VIDEO_CAPTURE,
+	VIDIOC_QUERYCAP,
+};
+
+use super::{format::c_str, open_device};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+	pub path: PathBuf,
+	pub card: String,
+	pub driver: String,
+	// e.g. usb-0000:00:14.0-2, stable as long as the camera stays in the same port
+	pub bus_info: String,
+	// from the USB device in sysfs, not every camera has one
+	pub serial: Option<String>,
+	pub capabilities: u32,
+}
+
+impl DeviceInfo {
+	pub fn query(path: impl AsRef<Path>, dev: &impl AsFd) -> io::Result<Self> {
+		let mut cap: v4l2_capability = unsafe { mem::zeroed() };
+		unsafe { v4l2_query_cap(dev.as_fd().as_raw_fd(), &mut cap)? };
+		// capabilities covers the whole physical device, device_caps just this node
+		let capabilities = if cap.capabilities & V4L2_CAP_DEVICE_CAPS != 0 {
+			cap.device_caps
+		} else {
+			cap.capabilities
+		};
+		Ok(Self {
+			path: path.as_ref().to_path_buf(),
+			card: c_str(&cap.card),
+			driver: c_str(&cap.driver),
+			bus_info: c_str(&cap.bus_info),
+			serial: sysfs_serial(dev),
+			capabilities,
+		})
+	}
+
+	// UVC cameras also expose a metadata node, which can't stream video
+	pub fn is_capture(&self) -> bool {
+		let needed = V4L2_CAP_VIDEO_CAPTURE | V4L2_CAP_STREAMING;
+		self.capabilities & needed == needed
+	}
+}
+
+// Capture nodes only, sorted by path
+pub fn enumerate_devices() -> io::Result<Vec<DeviceInfo>> {
+	let mut devices = Vec::new();
+	for entry in fs::read_dir("/dev")? {
+		let path = entry?.path();
+		let is_video = path
+			.file_name()
+			.and_then(|name| name.to_str())
+			.is_some_and(|name| name.starts_with("video"));
+		if !is_video {
+			continue;
+		}
+		// nodes we can't open (permissions, already gone) just aren't candidates
+		let Ok(dev) = open_device(&path) else {
+			continue;
+		};
+		match DeviceInfo::query(&path, &dev) {
+			Ok(info) if info.is_capture() => devices.push(info),
+			_ => {}
+		}
+	}
+	devices.sort_by(|a, b| a.path.cmp(&b.path));
+	Ok(devices)
+}
+
+// Walks up from the video node's sysfs device (the USB interface) to the USB device, the
+// first ancestor with idVendor, and reads its serial. Stops there: further up is the root
+// hub, whose serial is the host controller's address and shared by every camera on it.
+fn sysfs_serial(dev: &impl AsFd) -> Option<String> {
+	let rdev = fstat(dev.as_fd().as_raw_fd()).ok()?.st_rdev;
+	let sys = fs::canonicalize(format!("/sys/dev/char/{}:{}", major(rdev), minor(rdev))).ok()?;
+	let usb = sys
+		.ancestors()
+		.take_while(|dir| *dir != Path::new("/sys/devices"))
+		.find(|dir| dir.join("idVendor").exists())?;
+	fs::read_to_string(usb.join("serial"))
+		.ok()
+		.map(|serial| serial.trim().to_string())
+		.filter(|serial| !serial.is_empty())
+}
+
+// Which camera to open. Parses `serial:<serial>`, `bus:<bus info>`, or else a device path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+	Path(PathBuf),
+	Serial(String),
+	Bus(String),
+}
+
+impl DeviceSelector {
+	pub fn resolve(&self) -> io::Result<PathBuf> {
+		// any path is taken as is, including the /dev/v4l/by-* symlinks
+		if let Self::Path(path) = self {
+			return Ok(path.clone());
+		}
+		let matches = |info: &DeviceInfo| match self {
+			Self::Path(_) => false,
+			Self::Serial(serial) => info.serial.as_deref() == Some(serial.as_str()),
+			Self::Bus(bus) => info.bus_info == *bus,
+		};
+		let found: Vec<DeviceInfo> = enumerate_devices()?.into_iter().filter(matches).collect();
+		match found.as_slice() {
+			[info] => Ok(info.path.clone()),
+			[] => Err(io::Error::new(
+				io::ErrorKind::NotFound,
+				format!("No capture device matches {self}"),
+			)),
+			_ => Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("{} capture devices match {self}", found.len()),
+			)),
+		}
+	}
+}
+
+impl FromStr for DeviceSelector {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(if let Some(serial) = s.strip_prefix("serial:") {
+			Self::Serial(serial.to_string())
+		} else if let Some(bus) = s.strip_prefix("bus:") {
+			Self::Bus(bus.to_string())
+		} else {
+			Self::Path(s.into())
+		})
+	}
+}
+
+impl std::fmt::Display for DeviceSelector {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Path(path) => write!(f, "{}", path.display()),
+			Self::Serial(serial) => write!(f, "serial:{serial}"),
+			Self::Bus(bus) => write!(f, "bus:{bus}"),
+		}
+	}
+}
+
+ioctl_read_bad!(v4l2_query_cap, VIDIOC_QUERYCAP, v4l2_capability);
@@ -0,0 +1,52 @@
+use std::{
+	fmt, io,
+	path::{Path, PathBuf},
+};
+
+use nix::errno::Errno;
+
+#[derive(Debug)]
+pub enum CameraError {
+	// unplugged, or the USB link reset, the Camera has to be reopened
+	Disconnected(PathBuf),
+	Io(io::Error),
+}
+
+impl CameraError {
+	// uvcvideo fails DQBUF/QBUF with ENODEV once the device is gone, and vb2 with EIO when
+	// the driver flagged the queue as broken
+	pub(super) fn from_io(err: io::Error, path: &Path) -> Self {
+		match err.raw_os_error().map(Errno::from_raw) {
+			Some(Errno::ENODEV | Errno::EIO) => Self::Disconnected(path.to_path_buf()),
+			_ => Self::Io(err),
+		}
+	}
+
+	pub fn is_disconnected(&self) -> bool {
+		matches!(self, Self::Disconnected(_))
+	}
+}
+
+impl fmt::Display for CameraError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Disconnected(path) => write!(f, "{} disconnected", path.display()),
+			Self::Io(err) => err.fmt(f),
+		}
+	}
+}
+
+impl std::error::Error for CameraError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Disconnected(_) => None,
+			Self::Io(err) => Some(err),
+		}
+	}
+}
+
+impl From<io::Error> for CameraError {
+	fn from(err: io::Error) -> Self {
+		Self::Io(err)
+	}
+}
@@ -0,0 +1,194 @@
+use std::{
+	io, mem,
+	os::fd::{AsRawFd, OwnedFd},
+	path::PathBuf,
+	sync::Arc,
+	time::Duration,
+};
+
+use futures::{stream, Stream};
+use nix::{errno::Errno, ioctl_read_bad, ioctl_write_ptr_bad};
+use tokio::io::{unix::AsyncFd, Interest};
+use v4l2_sys::{
+	v4l2_event, v4l2_event_subscription, V4L2_EVENT_ALL, V4L2_EVENT_CTRL, V4L2_EVENT_EOS,
+	V4L2_EVENT_SOURCE_CHANGE, V4L2_EVENT_SRC_CH_RESOLUTION, VIDIOC_DQEVENT, VIDIOC_SUBSCRIBE_EVENT,
+	VIDIOC_UNSUBSCRIBE_EVENT,
+};
+
+use super::{controls::ControlType, CameraError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+	Control,
+	SourceChange,
+	EndOfStream,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraEvent {
+	// `changes` is the V4L2_EVENT_CTRL_CH_* bits for what changed: the value, the flags
+	// (e.g. becoming inactive when an auto mode is switched on) or the range
+	Control {
+		id: u32,
+		value: i64,
+		flags: u32,
+		changes: u32,
+		minimum: i32,
+		maximum: i32,
+		step: i32,
+	},
+	// the format changed under us, it has to be read again before streaming
+	SourceChange {
+		resolution: bool,
+	},
+	EndOfStream,
+	Other(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+	pub event: CameraEvent,
+	pub sequence: u32,
+	// CLOCK_MONOTONIC
+	pub timestamp: Duration,
+	// still queued after this one
+	pub pending: u32,
+}
+
+impl From<&v4l2_event> for Event {
+	fn from(ev: &v4l2_event) -> Self {
+		let event = match ev.type_ {
+			V4L2_EVENT_CTRL => {
+				let ctrl = unsafe { ev.u.ctrl };
+				let value = if ControlType::from(ctrl.type_) == ControlType::Integer64 {
+					unsafe { ctrl.__bindgen_anon_1.value64 }
+				} else {
+					unsafe { ctrl.__bindgen_anon_1.value as i64 }
+				};
+				CameraEvent::Control {
+					id: ev.id,
+					value,
+					flags: ctrl.flags,
+					changes: ctrl.changes,
+					minimum: ctrl.minimum,
+					maximum: ctrl.maximum,
+					step: ctrl.step,
+				}
+			}
+			V4L2_EVENT_SOURCE_CHANGE => CameraEvent::SourceChange {
+				resolution: unsafe { ev.u.src_change }.changes & V4L2_EVENT_SRC_CH_RESOLUTION != 0,
+			},
+			V4L2_EVENT_EOS => CameraEvent::EndOfStream,
+			other => CameraEvent::Other(other),
+		};
+		Self {
+			event,
+			sequence: ev.sequence,
+			timestamp: Duration::new(ev.timestamp.tv_sec as u64, ev.timestamp.tv_nsec as u32),
+			pending: ev.pending,
+		}
+	}
+}
+
+// Events are signalled as priority data (POLLPRI) on the device, so they can be waited on
+// while frames are streaming. Subscriptions belong to the open file rather than to this,
+// so only have one of these per camera at a time, dropping it unsubscribes from everything.
+pub struct CameraEvents {
+	dev: Arc<AsyncFd<OwnedFd>>,
+	path: PathBuf,
+	kinds: Vec<EventKind>,
+}
+
+impl CameraEvents {
+	// Control events need a subscription per control, hence `controls`. Kinds the driver
+	// doesn't support (UVC only has control events) are skipped, see `kinds`.
+	pub(super) fn subscribe(
+		dev: Arc<AsyncFd<OwnedFd>>,
+		path: PathBuf,
+		kinds: &[EventKind],
+		controls: &[u32],
+	) -> io::Result<Self> {
+		let mut events = Self {
+			dev,
+			path,
+			kinds: Vec::new(),
+		};
+		for &kind in kinds {
+			let res = match kind {
+				EventKind::Control => controls
+					.iter()
+					.try_for_each(|&id| subscribe(&events.dev, V4L2_EVENT_CTRL, id)),
+				EventKind::SourceChange => subscribe(&events.dev, V4L2_EVENT_SOURCE_CHANGE, 0),
+				EventKind::EndOfStream => subscribe(&events.dev, V4L2_EVENT_EOS, 0),
+			};
+			match res {
+				Ok(()) => events.kinds.push(kind),
+				Err(Errno::EINVAL) => {}
+				Err(e) => return Err(e.into()),
+			}
+		}
+		Ok(events)
+	}
+
+	// What the driver accepted subscriptions for
+	pub fn kinds(&self) -> &[EventKind] {
+		&self.kinds
+	}
+
+	// Changes made through this process's own `Camera::set` aren't reported, only other
+	// processes' and the camera's. A disconnect is reported on the frame stream, there's
+	// no event for it.
+	pub async fn next(&mut self) -> Result<Event, CameraError> {
+		self.dev
+			.async_io(Interest::PRIORITY, |dev| {
+				let mut ev: v4l2_event = unsafe { mem::zeroed() };
+				match unsafe { v4l2_dequeue_event(dev.as_raw_fd(), &mut ev) } {
+					Ok(_) => Ok(Event::from(&ev)),
+					// the queue is empty
+					Err(Errno::ENOENT) => Err(io::ErrorKind::WouldBlock.into()),
+					Err(e) => Err(e.into()),
+				}
+			})
+			.await
+			.map_err(|e| CameraError::from_io(e, &self.path))
+	}
+
+	// Ends after a disconnect
+	pub fn into_stream(self) -> impl Stream<Item = Result<Event, CameraError>> {
+		stream::unfold(Some(self), |events| async move {
+			let mut events = events?;
+			let res = events.next().await;
+			let done = matches!(&res, Err(e) if e.is_disconnected());
+			Some((res, (!done).then_some(events)))
+		})
+	}
+}
+
+impl Drop for CameraEvents {
+	fn drop(&mut self) {
+		let mut sub: v4l2_event_subscription = unsafe { mem::zeroed() };
+		sub.type_ = V4L2_EVENT_ALL;
+		// fails once the device is gone, which drops the subscriptions anyway
+		let _ = unsafe { v4l2_unsubscribe_event(self.dev.as_raw_fd(), &sub) };
+	}
+}
+
+fn subscribe(dev: &impl AsRawFd, type_: u32, id: u32) -> Result<(), Errno> {
+	let mut sub: v4l2_event_subscription = unsafe { mem::zeroed() };
+	sub.type_ = type_;
+	sub.id = id;
+	unsafe { v4l2_subscribe_event(dev.as_raw_fd(), &sub) }?;
+	Ok(())
+}
+
+ioctl_write_ptr_bad!(
+	v4l2_subscribe_event,
+	VIDIOC_SUBSCRIBE_EVENT,
+	v4l2_event_subscription
+);
+ioctl_write_ptr_bad!(
+	v4l2_unsubscribe_event,
+	VIDIOC_UNSUBSCRIBE_EVENT,
+	v4l2_event_subscription
+);
+ioctl_read_bad!(v4l2_dequeue_event, VIDIOC_DQEVENT, v4l2_event);
@@ -0,0 +1,237 @@
+use v4l2_sys::v4l2_exposure_auto_type_V4L2_EXPOSURE_MANUAL;
+
+use super::{
+	controls::{ControlInfo, ControlValue},
+	convert::Yuv444,
+	Camera, CameraError, Exposure, ExposureAuto, Gain,
+};
+
+// Luma at or above this counts as clipped
+const CLIPPED: u8 = 250;
+
+// Region the exposure is measured over, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Roi {
+	pub x: usize,
+	pub y: usize,
+	pub width: usize,
+	pub height: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExposureSettings {
+	// mean luma to aim for, 0-255
+	pub target: f64,
+	// how far off the mean can be and still count as on target
+	pub tolerance: f64,
+	// fraction of pixels allowed to be clipped, overrides `target` when exceeded
+	pub max_clipped: f64,
+	// whole frame if None
+	pub roi: Option<Roi>,
+	// longest exposure to use before raising gain instead, in the Exposure control's units
+	// (100µs for UVC). Longer exposures blur when the arm vibrates.
+	pub max_exposure: Option<i32>,
+	// gives up and locks whatever it got to after this many adjustments
+	pub max_iterations: usize,
+	// frames to drop after each adjustment, on top of the ones already queued, since
+	// cameras take a frame or two to apply a new exposure
+	pub settle_frames: usize,
+}
+
+impl Default for ExposureSettings {
+	fn default() -> Self {
+		Self {
+			target: 110.,
+			tolerance: 8.,
+			max_clipped: 0.01,
+			roi: None,
+			max_exposure: None,
+			max_iterations: 20,
+			settle_frames: 2,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct LumaStats {
+	pub histogram: [u32; 256],
+	pub mean: f64,
+	// fraction of pixels at or above `CLIPPED`
+	pub clipped: f64,
+}
+
+impl LumaStats {
+	// `roi` is clamped to the frame
+	pub fn measure(y: &[u8], width: usize, height: usize, roi: Option<Roi>) -> Self {
+		let roi = roi.unwrap_or(Roi {
+			x: 0,
+			y: 0,
+			width,
+			height,
+		});
+		let x0 = roi.x.min(width);
+		let x1 = (roi.x + roi.width).min(width);
+		let y0 = roi.y.min(height);
+		let y1 = (roi.y + roi.height).min(height);
+
+		let mut histogram = [0; 256];
+		for row in y0..y1 {
+			for &px in &y[row * width + x0..row * width + x1] {
+				histogram[px as usize] += 1;
+			}
+		}
+		let pixels: u64 = histogram.iter().map(|&n| n as u64).sum();
+		let sum: u64 = histogram
+			.iter()
+			.enumerate()
+			.map(|(luma, &n)| luma as u64 * n as u64)
+			.sum();
+		let clipped: u64 = histogram[CLIPPED as usize..]
+			.iter()
+			.map(|&n| n as u64)
+			.sum();
+		let pixels_f = pixels.max(1) as f64;
+		Self {
+			histogram,
+			mean: sum as f64 / pixels_f,
+			clipped: clipped as f64 / pixels_f,
+		}
+	}
+}
+
+// What `lock_exposure` settled on. The camera keeps these until it's reset, pass `values`
+// to `SupervisedCamera::set_many` to have them survive a reconnect too.
+#[derive(Debug, Clone)]
+pub struct ExposureLock {
+	pub exposure: i32,
+	pub gain: i32,
+	pub stats: LumaStats,
+	pub iterations: usize,
+	// false if it ran out of iterations or controls to turn
+	pub converged: bool,
+}
+
+impl ExposureLock {
+	pub fn values(&self) -> Vec<ControlValue> {
+		vec![
+			ControlValue::of::<ExposureAuto>(v4l2_exposure_auto_type_V4L2_EXPOSURE_MANUAL as i32),
+			ControlValue::of::<Exposure>(self.exposure),
+			ControlValue::of::<Gain>(self.gain),
+		]
+	}
+}
+
+// Turns the camera's own auto exposure off and adjusts Exposure, then Gain once exposure
+// hits its limit, until the frames' mean luma is on target without too much clipping. The
+// values are left set, so every later frame is exposed the same regardless of what's in
+// view. Starts the stream if it isn't already.
+pub async fn lock_exposure(
+	cam: &mut Camera,
+	settings: ExposureSettings,
+) -> Result<ExposureLock, CameraError> {
+	cam.set::<ExposureAuto>(v4l2_exposure_auto_type_V4L2_EXPOSURE_MANUAL as i32)?;
+	let exposure_info = cam.control::<Exposure>()?;
+	// not every camera has a gain control
+	let gain_info = cam.control::<Gain>().ok();
+	let max_exposure = settings
+		.max_exposure
+		.map_or(exposure_info.maximum, |max| max.min(exposure_info.maximum));
+
+	let mut exposure = cam.get::<Exposure>()?;
+	let mut gain = match gain_info {
+		Some(_) => cam.get::<Gain>()?,
+		None => 0,
+	};
+
+	let format = cam.format();
+	let mut planes = Yuv444::new(format.width, format.height);
+
+	let mut iterations = 0;
+	loop {
+		// frames already queued were exposed with the old values
+		cam.settled_frame(&mut planes, settings.settle_frames)
+			.await?;
+		let stats = LumaStats::measure(&planes.y, planes.width, planes.height, settings.roi);
+
+		let on_target = (stats.mean - settings.target).abs() <= settings.tolerance;
+		let clipping = stats.clipped > settings.max_clipped;
+		if (on_target && !clipping) || iterations == settings.max_iterations {
+			return Ok(ExposureLock {
+				exposure,
+				gain,
+				stats,
+				iterations,
+				converged: on_target && !clipping,
+			});
+		}
+		iterations += 1;
+
+		// brightness is roughly proportional to exposure time, and clipping hides how far
+		// over it is, so back off by a fixed step then
+		let mut ratio = settings.target / stats.mean.max(1.);
+		if clipping {
+			ratio = ratio.min(0.7);
+		}
+		let (next_exposure, next_gain) = adjust(
+			ratio,
+			exposure,
+			gain,
+			&exposure_info,
+			max_exposure,
+			gain_info.as_ref(),
+		);
+		if (next_exposure, next_gain) == (exposure, gain) {
+			// at the end of both ranges
+			return Ok(ExposureLock {
+				exposure,
+				gain,
+				stats,
+				iterations,
+				converged: false,
+			});
+		}
+		exposure = cam.set::<Exposure>(next_exposure)?;
+		if gain_info.is_some() && next_gain != gain {
+			gain = cam.set::<Gain>(next_gain)?;
+		}
+	}
+}
+
+// Gain adds noise, so brightening raises exposure first and darkening lowers gain first.
+// Gain's units vary between cameras, it's moved by a share of its range instead.
+fn adjust(
+	ratio: f64,
+	exposure: i32,
+	gain: i32,
+	exposure_info: &ControlInfo,
+	max_exposure: i32,
+	gain_info: Option<&ControlInfo>,
+) -> (i32, i32) {
+	let scaled = |value: i32| (value.max(1) as f64 * ratio).round() as i32;
+	let gain_step = |info: &ControlInfo| {
+		let span = (info.maximum - info.minimum) as f64;
+		(span * (ratio - 1.) / 2.).round() as i32
+	};
+	if ratio > 1. {
+		if exposure < max_exposure {
+			let next = scaled(exposure).clamp(exposure + 1, max_exposure);
+			return (exposure_info.clamp(next), gain);
+		}
+		if let Some(info) = gain_info {
+			let step = gain_step(info).max(info.step.max(1));
+			return (exposure, info.clamp((gain + step).min(info.maximum)));
+		}
+	} else {
+		if let Some(info) = gain_info {
+			if gain > info.minimum {
+				let step = gain_step(info).min(-info.step.max(1));
+				return (exposure, info.clamp((gain + step).max(info.minimum)));
+			}
+		}
+		if exposure > exposure_info.minimum {
+			let next = scaled(exposure).clamp(exposure_info.minimum, exposure - 1);
+			return (exposure_info.clamp(next), gain);
+		}
+	}
+	(exposure, gain)
+}
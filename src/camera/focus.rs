@@ -0,0 +1,188 @@
+use std::io;
+
+use super::{convert::Yuv444, selection::Rect, Camera, CameraError, FocusAbsolute, FocusAuto};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SharpnessMetric {
+	// variance of the 4-neighbour Laplacian, quick but sensitive to noise
+	#[default]
+	LaplacianVariance,
+	// mean squared Sobel gradient magnitude, steadier on noisy or dim frames
+	Tenengrad,
+}
+
+// Higher is sharper. Only comparable between frames of the same scene and size, the
+// absolute value depends on content. `roi` is clamped to the frame, whole frame if None.
+pub fn sharpness(
+	luma: &[u8],
+	width: usize,
+	height: usize,
+	roi: Option<Rect>,
+	metric: SharpnessMetric,
+) -> f64 {
+	let (x0, y0, x1, y1) = match roi {
+		Some(r) => (
+			(r.left.max(0) as usize).min(width),
+			(r.top.max(0) as usize).min(height),
+			(r.left.max(0) as usize + r.width as usize).min(width),
+			(r.top.max(0) as usize + r.height as usize).min(height),
+		),
+		None => (0, 0, width, height),
+	};
+	// both kernels need a pixel on every side
+	let (x0, y0) = (x0.max(1), y0.max(1));
+	let (x1, y1) = (
+		x1.min(width.saturating_sub(1)),
+		y1.min(height.saturating_sub(1)),
+	);
+	if x0 >= x1 || y0 >= y1 {
+		return 0.;
+	}
+	let px = |x: usize, y: usize| luma[y * width + x] as f64;
+
+	let mut sum = 0.;
+	let mut sum_sq = 0.;
+	for y in y0..y1 {
+		for x in x0..x1 {
+			match metric {
+				SharpnessMetric::LaplacianVariance => {
+					let l =
+						4. * px(x, y) - px(x - 1, y) - px(x + 1, y) - px(x, y - 1) - px(x, y + 1);
+					sum += l;
+					sum_sq += l * l;
+				}
+				SharpnessMetric::Tenengrad => {
+					let gx = px(x + 1, y - 1) + 2. * px(x + 1, y) + px(x + 1, y + 1)
+						- px(x - 1, y - 1) - 2. * px(x - 1, y)
+						- px(x - 1, y + 1);
+					let gy = px(x - 1, y + 1) + 2. * px(x, y + 1) + px(x + 1, y + 1)
+						- px(x - 1, y - 1) - 2. * px(x, y - 1)
+						- px(x + 1, y - 1);
+					sum_sq += gx * gx + gy * gy;
+				}
+			}
+		}
+	}
+	let n = ((x1 - x0) * (y1 - y0)) as f64;
+	match metric {
+		SharpnessMetric::LaplacianVariance => sum_sq / n - (sum / n).powi(2),
+		SharpnessMetric::Tenengrad => sum_sq / n,
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FocusSweepSettings {
+	// FocusAbsolute's whole range if None
+	pub min: Option<i32>,
+	pub max: Option<i32>,
+	// positions tried across the range, then again across the two steps around the best
+	pub steps: usize,
+	pub metric: SharpnessMetric,
+	// whole frame if None, e.g. the part of the frame the target is in
+	pub roi: Option<Rect>,
+	// frames to drop after each move on top of the ones already queued, the lens takes a
+	// moment to get there
+	pub settle_frames: usize,
+}
+
+impl Default for FocusSweepSettings {
+	fn default() -> Self {
+		Self {
+			min: None,
+			max: None,
+			steps: 16,
+			metric: SharpnessMetric::default(),
+			roi: None,
+			settle_frames: 3,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct FocusSweep {
+	// what FocusAbsolute is left at
+	pub best: i32,
+	pub sharpness: f64,
+	// (focus, sharpness) for every position tried, in order
+	pub samples: Vec<(i32, f64)>,
+}
+
+impl Camera {
+	// Turns autofocus off, steps FocusAbsolute through its range measuring sharpness at
+	// each position, refines around the sharpest, and leaves focus there. Starts the stream
+	// if it isn't already.
+	pub async fn focus_sweep(
+		&mut self,
+		settings: FocusSweepSettings,
+	) -> Result<FocusSweep, CameraError> {
+		// fixed focus cameras have FocusAbsolute without FocusAuto
+		if self.control::<FocusAuto>().is_ok() {
+			self.set::<FocusAuto>(0)?;
+		}
+		let info = self.control::<FocusAbsolute>()?;
+		let min = settings.min.map_or(info.minimum, |m| m.max(info.minimum));
+		let max = settings.max.map_or(info.maximum, |m| m.min(info.maximum));
+		if min > max {
+			return Err(CameraError::Io(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("Focus range {min}..={max} is empty"),
+			)));
+		}
+		let steps = settings.steps.max(2);
+
+		let format = self.format();
+		let mut planes = Yuv444::new(format.width, format.height);
+		let mut samples = Vec::new();
+
+		let mut range = (min, max);
+		for _pass in 0..2 {
+			let (lo, hi) = range;
+			let step = ((hi - lo) as f64 / (steps - 1) as f64).max(info.step.max(1) as f64);
+			let mut positions: Vec<i32> = (0..steps)
+				.map(|i| info.clamp((lo as f64 + step * i as f64).round() as i32))
+				.filter(|&focus| focus <= hi)
+				.collect();
+			// a range narrower than the control's step can have every position snap past
+			// its end, the one nearest its start is tried then
+			if positions.is_empty() {
+				positions.push(info.clamp(lo));
+			}
+			positions.dedup();
+			for focus in positions {
+				if samples.iter().any(|&(f, _)| f == focus) {
+					continue;
+				}
+				let focus = self.set::<FocusAbsolute>(focus)?;
+				self.settled_frame(&mut planes, settings.settle_frames)
+					.await?;
+				let value = sharpness(
+					&planes.y,
+					planes.width,
+					planes.height,
+					settings.roi,
+					settings.metric,
+				);
+				samples.push((focus, value));
+			}
+			let (best, _) = best(&samples);
+			let step = step.ceil() as i32;
+			range = ((best - step).max(min), (best + step).min(max));
+		}
+
+		let (best, sharpness) = best(&samples);
+		self.set::<FocusAbsolute>(best)?;
+		Ok(FocusSweep {
+			best,
+			sharpness,
+			samples,
+		})
+	}
+}
+
+fn best(samples: &[(i32, f64)]) -> (i32, f64) {
+	samples
+		.iter()
+		.copied()
+		.max_by(|a, b| a.1.total_cmp(&b.1))
+		.expect("the range isn't empty, so at least one position is tried")
+}
@@ -0,0 +1,328 @@
+use std::{io, mem, os::fd::AsRawFd};
+
+use nix::{errno::Errno, ioctl_readwrite_bad};
+use v4l2_sys::{
+	v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE, v4l2_fmtdesc, v4l2_fract, v4l2_frmivalenum,
+	v4l2_frmivaltypes_V4L2_FRMIVAL_TYPE_DISCRETE, v4l2_frmsizeenum,
+	v4l2_frmsizetypes_V4L2_FRMSIZE_TYPE_DISCRETE, v4l2_streamparm, V4L2_CAP_TIMEPERFRAME,
+	V4L2_FMT_FLAG_COMPRESSED, VIDIOC_ENUM_FMT, VIDIOC_ENUM_FRAMEINTERVALS, VIDIOC_ENUM_FRAMESIZES,
+	VIDIOC_G_PARM, VIDIOC_S_PARM,
+};
+
+use super::VideoPixelFormat;
+
+pub const fn fourcc(code: &[u8; 4]) -> u32 {
+	u32::from_le_bytes(*code)
+}
+
+pub fn fourcc_name(fourcc: u32) -> String {
+	String::from_utf8_lossy(&fourcc.to_le_bytes()).into_owned()
+}
+
+// Uncompressed formats `convert::Yuv444` handles, alongside MJPEG
+pub const YUYV_FMT: u32 = fourcc(b"YUYV");
+pub const UYVY_FMT: u32 = fourcc(b"UYVY");
+pub const NV12_FMT: u32 = fourcc(b"NV12");
+pub const GREY_FMT: u32 = fourcc(b"GREY");
+pub const Y16_FMT: u32 = fourcc(b"Y16 ");
+// Three full resolution planes back to back, what `ReplayCamera` gives for recordings
+pub const YUV444M_FMT: u32 = fourcc(b"YM24");
+
+// Time per frame, so 1/30 is 30fps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fraction {
+	pub numerator: u32,
+	pub denominator: u32,
+}
+
+impl Fraction {
+	pub fn new(numerator: u32, denominator: u32) -> Self {
+		Self {
+			numerator,
+			denominator,
+		}
+	}
+
+	pub fn as_f64(&self) -> f64 {
+		self.numerator as f64 / self.denominator as f64
+	}
+
+	pub fn fps(&self) -> f64 {
+		self.denominator as f64 / self.numerator as f64
+	}
+
+	// 1/30 and 2/60 are the same interval
+	pub fn same_as(&self, other: Fraction) -> bool {
+		self.numerator as u64 * other.denominator as u64
+			== other.numerator as u64 * self.denominator as u64
+	}
+}
+
+impl From<v4l2_fract> for Fraction {
+	fn from(f: v4l2_fract) -> Self {
+		Self::new(f.numerator, f.denominator)
+	}
+}
+
+impl From<Fraction> for v4l2_fract {
+	fn from(f: Fraction) -> Self {
+		v4l2_fract {
+			numerator: f.numerator,
+			denominator: f.denominator,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameIntervals {
+	Discrete(Vec<Fraction>),
+	// continuous ranges are reported as stepwise with a 1/1 step
+	Stepwise {
+		min: Fraction,
+		max: Fraction,
+		step: Fraction,
+	},
+}
+
+impl FrameIntervals {
+	pub fn contains(&self, interval: Fraction) -> bool {
+		match self {
+			Self::Discrete(intervals) => intervals.iter().any(|i| i.same_as(interval)),
+			Self::Stepwise { min, max, step } => {
+				if !(min.as_f64()..=max.as_f64()).contains(&interval.as_f64()) {
+					return false;
+				}
+				// continuous, see above
+				if step.same_as(Fraction::new(1, 1)) || step.numerator == 0 {
+					return true;
+				}
+				// (interval - min) / step has to be whole, cross multiplied to stay exact
+				let offset = interval.numerator as i128 * min.denominator as i128
+					- min.numerator as i128 * interval.denominator as i128;
+				let per_step =
+					interval.denominator as i128 * min.denominator as i128 * step.numerator as i128;
+				(offset * step.denominator as i128) % per_step == 0
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameSize {
+	pub width: u32,
+	pub height: u32,
+	pub intervals: FrameIntervals,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameSizes {
+	Discrete(Vec<FrameSize>),
+	Stepwise {
+		min_width: u32,
+		max_width: u32,
+		step_width: u32,
+		min_height: u32,
+		max_height: u32,
+		step_height: u32,
+	},
+}
+
+impl FrameSizes {
+	pub fn contains(&self, width: u32, height: u32) -> bool {
+		match *self {
+			Self::Discrete(ref sizes) => {
+				sizes.iter().any(|s| s.width == width && s.height == height)
+			}
+			Self::Stepwise {
+				min_width,
+				max_width,
+				step_width,
+				min_height,
+				max_height,
+				step_height,
+			} => {
+				(min_width..=max_width).contains(&width)
+					&& (min_height..=max_height).contains(&height)
+					&& (width - min_width).is_multiple_of(step_width.max(1))
+					&& (height - min_height).is_multiple_of(step_height.max(1))
+			}
+		}
+	}
+
+	pub fn get(&self, width: u32, height: u32) -> Option<&FrameSize> {
+		match self {
+			Self::Discrete(sizes) => sizes
+				.iter()
+				.find(|s| s.width == width && s.height == height),
+			Self::Stepwise { .. } => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatInfo {
+	pub fourcc: u32,
+	pub description: String,
+	pub compressed: bool,
+	pub sizes: FrameSizes,
+}
+
+pub fn enumerate_formats(dev: &impl AsRawFd) -> io::Result<Vec<FormatInfo>> {
+	let mut formats = Vec::new();
+	for index in 0.. {
+		let mut desc: v4l2_fmtdesc = unsafe { mem::zeroed() };
+		desc.index = index;
+		desc.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
+		match unsafe { v4l2_enum_fmt(dev.as_raw_fd(), &mut desc) } {
+			Ok(_) => {}
+			// EINVAL marks the end of the list
+			Err(Errno::EINVAL) => break,
+			Err(e) => return Err(e.into()),
+		}
+		formats.push(FormatInfo {
+			fourcc: desc.pixelformat,
+			description: c_str(&desc.description),
+			compressed: desc.flags & V4L2_FMT_FLAG_COMPRESSED != 0,
+			sizes: enumerate_sizes(dev, desc.pixelformat)?,
+		});
+	}
+	Ok(formats)
+}
+
+fn enumerate_sizes(dev: &impl AsRawFd, fourcc: u32) -> io::Result<FrameSizes> {
+	let mut sizes = Vec::new();
+	for index in 0.. {
+		let mut size: v4l2_frmsizeenum = unsafe { mem::zeroed() };
+		size.index = index;
+		size.pixel_format = fourcc;
+		match unsafe { v4l2_enum_framesizes(dev.as_raw_fd(), &mut size) } {
+			Ok(_) => {}
+			Err(Errno::EINVAL) => break,
+			Err(e) => return Err(e.into()),
+		}
+		if size.type_ != v4l2_frmsizetypes_V4L2_FRMSIZE_TYPE_DISCRETE {
+			// stepwise and continuous are only ever reported once, at index 0
+			let s = unsafe { size.__bindgen_anon_1.stepwise };
+			return Ok(FrameSizes::Stepwise {
+				min_width: s.min_width,
+				max_width: s.max_width,
+				step_width: s.step_width,
+				min_height: s.min_height,
+				max_height: s.max_height,
+				step_height: s.step_height,
+			});
+		}
+		let d = unsafe { size.__bindgen_anon_1.discrete };
+		sizes.push(FrameSize {
+			width: d.width,
+			height: d.height,
+			intervals: enumerate_intervals(dev, fourcc, d.width, d.height)?,
+		});
+	}
+	Ok(FrameSizes::Discrete(sizes))
+}
+
+pub(super) fn enumerate_intervals(
+	dev: &impl AsRawFd,
+	fourcc: u32,
+	width: u32,
+	height: u32,
+) -> io::Result<FrameIntervals> {
+	let mut intervals = Vec::new();
+	for index in 0.. {
+		let mut ival: v4l2_frmivalenum = unsafe { mem::zeroed() };
+		ival.index = index;
+		ival.pixel_format = fourcc;
+		ival.width = width;
+		ival.height = height;
+		match unsafe { v4l2_enum_frameintervals(dev.as_raw_fd(), &mut ival) } {
+			Ok(_) => {}
+			Err(Errno::EINVAL) => break,
+			Err(e) => return Err(e.into()),
+		}
+		if ival.type_ != v4l2_frmivaltypes_V4L2_FRMIVAL_TYPE_DISCRETE {
+			let s = unsafe { ival.__bindgen_anon_1.stepwise };
+			return Ok(FrameIntervals::Stepwise {
+				min: s.min.into(),
+				max: s.max.into(),
+				step: s.step.into(),
+			});
+		}
+		intervals.push(unsafe { ival.__bindgen_anon_1.discrete }.into());
+	}
+	Ok(FrameIntervals::Discrete(intervals))
+}
+
+pub(super) fn frame_interval(dev: &impl AsRawFd) -> io::Result<Fraction> {
+	let parm = stream_params(dev)?;
+	Ok(unsafe { parm.parm.capture.timeperframe }.into())
+}
+
+// Checks `interval` against what the driver lists for `format` first, since S_PARM
+// otherwise quietly rounds to the nearest interval it supports.
+pub(super) fn set_frame_interval(
+	dev: &impl AsRawFd,
+	format: VideoPixelFormat,
+	interval: Fraction,
+) -> io::Result<Fraction> {
+	let supported = enumerate_intervals(dev, format.format, format.width, format.height)?;
+	if !supported.contains(interval) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!(
+				"{}/{}s frames aren't supported at {}x{} {}, options are {supported:?}",
+				interval.numerator,
+				interval.denominator,
+				format.width,
+				format.height,
+				fourcc_name(format.format),
+			),
+		));
+	}
+	let mut parm = stream_params(dev)?;
+	if unsafe { parm.parm.capture.capability } & V4L2_CAP_TIMEPERFRAME == 0 {
+		return Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"Driver doesn't support setting the frame interval",
+		));
+	}
+	parm.parm.capture.timeperframe = interval.into();
+	unsafe { v4l2_set_parm(dev.as_raw_fd(), &mut parm)? };
+	let actual: Fraction = unsafe { parm.parm.capture.timeperframe }.into();
+	if !actual.same_as(interval) {
+		return Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			format!(
+				"Requested {}/{}s frames but the driver set {}/{}s",
+				interval.numerator, interval.denominator, actual.numerator, actual.denominator,
+			),
+		));
+	}
+	Ok(actual)
+}
+
+fn stream_params(dev: &impl AsRawFd) -> io::Result<v4l2_streamparm> {
+	let mut parm: v4l2_streamparm = unsafe { mem::zeroed() };
+	parm.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
+	unsafe { v4l2_get_parm(dev.as_raw_fd(), &mut parm)? };
+	Ok(parm)
+}
+
+pub(super) fn c_str(bytes: &[u8]) -> String {
+	let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+	String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+ioctl_readwrite_bad!(v4l2_enum_fmt, VIDIOC_ENUM_FMT, v4l2_fmtdesc);
+ioctl_readwrite_bad!(
+	v4l2_enum_framesizes,
+	VIDIOC_ENUM_FRAMESIZES,
+	v4l2_frmsizeenum
+);
+ioctl_readwrite_bad!(
+	v4l2_enum_frameintervals,
+	VIDIOC_ENUM_FRAMEINTERVALS,
+	v4l2_frmivalenum
+);
+ioctl_readwrite_bad!(v4l2_get_parm, VIDIOC_G_PARM, v4l2_streamparm);
+ioctl_readwrite_bad!(v4l2_set_parm, VIDIOC_S_PARM, v4l2_streamparm);
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use v4l2_sys::{
+	v4l2_buffer, V4L2_BUF_FLAG_ERROR, V4L2_BUF_FLAG_KEYFRAME, V4L2_BUF_FLAG_TIMESTAMP_COPY,
+	V4L2_BUF_FLAG_TIMESTAMP_MASK, V4L2_BUF_FLAG_TIMESTAMP_MONOTONIC, V4L2_BUF_FLAG_TSTAMP_SRC_MASK,
+	V4L2_BUF_FLAG_TSTAMP_SRC_SOE,
+};
+
+use super::validate::IntegrityStats;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampClock {
+	Unknown,
+	// CLOCK_MONOTONIC, comparable with robot::state::monotonic_now
+	Monotonic,
+	// copied from the output side, only for m2m devices
+	Copy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampSource {
+	EndOfFrame,
+	StartOfExposure,
+}
+
+// Metadata the driver fills in on dequeue
+#[derive(Debug, Clone, Copy)]
+pub struct FrameInfo {
+	// which of the camera's buffers the frame is in
+	pub index: u32,
+	pub sequence: u32,
+	pub timestamp: Duration,
+	pub timestamp_clock: TimestampClock,
+	pub timestamp_source: TimestampSource,
+	pub bytesused: u32,
+	pub flags: u32,
+}
+
+impl FrameInfo {
+	pub(super) fn from_buffer(buf: &v4l2_buffer) -> Self {
+		let timestamp_clock = match buf.flags & V4L2_BUF_FLAG_TIMESTAMP_MASK {
+			V4L2_BUF_FLAG_TIMESTAMP_MONOTONIC => TimestampClock::Monotonic,
+			V4L2_BUF_FLAG_TIMESTAMP_COPY => TimestampClock::Copy,
+			_ => TimestampClock::Unknown,
+		};
+		let timestamp_source = match buf.flags & V4L2_BUF_FLAG_TSTAMP_SRC_MASK {
+			V4L2_BUF_FLAG_TSTAMP_SRC_SOE => TimestampSource::StartOfExposure,
+			_ => TimestampSource::EndOfFrame,
+		};
+		Self {
+			index: buf.index,
+			sequence: buf.sequence,
+			timestamp: Duration::new(
+				buf.timestamp.tv_sec as u64,
+				buf.timestamp.tv_usec as u32 * 1000,
+			),
+			timestamp_clock,
+			timestamp_source,
+			bytesused: buf.bytesused,
+			flags: buf.flags,
+		}
+	}
+
+	// the driver hit a problem filling the buffer, the data may be corrupt
+	pub fn is_error(&self) -> bool {
+		self.flags & V4L2_BUF_FLAG_ERROR != 0
+	}
+
+	pub fn is_keyframe(&self) -> bool {
+		self.flags & V4L2_BUF_FLAG_KEYFRAME != 0
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'a> {
+	pub data: &'a [u8],
+	pub info: FrameInfo,
+	// frames the driver skipped between the previous frame and this one
+	pub dropped: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StreamStats {
+	pub frames: u64,
+	pub dropped: u64,
+	pub errors: u64,
+	// frames that failed validation, see `validate::decode_frame`
+	pub integrity: IntegrityStats,
+	first_timestamp: Option<Duration>,
+	last_timestamp: Option<Duration>,
+	last_sequence: Option<u32>,
+}
+
+impl StreamStats {
+	// returns how many frames were dropped right before this one
+	pub(super) fn record(&mut self, info: &FrameInfo) -> u32 {
+		let dropped = match self.last_sequence {
+			Some(last) => info.sequence.wrapping_sub(last).saturating_sub(1),
+			None => 0,
+		};
+		self.frames += 1;
+		self.dropped += dropped as u64;
+		if info.is_error() {
+			self.errors += 1;
+		}
+		self.first_timestamp.get_or_insert(info.timestamp);
+		self.last_timestamp = Some(info.timestamp);
+		self.last_sequence = Some(info.sequence);
+		dropped
+	}
+
+	// For a new stream on the same camera, e.g. after it was reopened, so the sequence
+	// restarting isn't counted as dropped frames
+	pub(super) fn restart(&mut self) {
+		self.last_sequence = None;
+	}
+
+	// from the driver timestamps, so it's the rate frames were captured at rather than
+	// the rate they were read
+	pub fn fps(&self) -> Option<f64> {
+		let span = self.last_timestamp?.checked_sub(self.first_timestamp?)?;
+		let intervals = (self.frames + self.dropped).checked_sub(1)?;
+		(!span.is_zero()).then(|| intervals as f64 / span.as_secs_f64())
+	}
+
+	pub fn drop_rate(&self) -> f64 {
+		let total = self.frames + self.dropped;
+		if total == 0 {
+			0.
+		} else {
+			self.dropped as f64 / total as f64
+		}
+	}
+}
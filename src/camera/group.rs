@@ -0,0 +1,218 @@
+use std::{collections::VecDeque, io, time::Duration};
+
+use tokio::{
+	sync::mpsc::{self, Receiver, Sender},
+	task::JoinHandle,
+};
+
+use super::{
+	frame::{FrameInfo, StreamStats},
+	Camera, CameraError,
+};
+
+// Owned, since it outlives the buffer it was read from while the other cameras catch up
+#[derive(Debug, Clone)]
+pub struct GroupFrame {
+	pub data: Vec<u8>,
+	pub info: FrameInfo,
+	pub dropped: u32,
+}
+
+// One frame from each camera, in the order the cameras were given
+#[derive(Debug, Clone)]
+pub struct FrameSet {
+	pub frames: Vec<GroupFrame>,
+	// latest minus earliest timestamp in the set
+	pub skew: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SkewStats {
+	pub matched: u64,
+	// frames thrown away for having no counterpart within the tolerance
+	pub unmatched: u64,
+	// of matched frames relative to the first camera's, in seconds and signed so a
+	// consistent offset shows up
+	pub max_offset: f64,
+	sum_offset: f64,
+}
+
+impl SkewStats {
+	pub fn mean_offset(&self) -> f64 {
+		if self.matched == 0 {
+			0.
+		} else {
+			self.sum_offset / self.matched as f64
+		}
+	}
+
+	fn record(&mut self, offset: f64) {
+		self.matched += 1;
+		self.sum_offset += offset;
+		if offset.abs() > self.max_offset.abs() {
+			self.max_offset = offset;
+		}
+	}
+}
+
+type Message = (usize, Result<(Vec<u8>, FrameInfo), CameraError>);
+
+// Streams several cameras at once, each on its own task, and pairs up their frames by
+// driver timestamp. Only meaningful when every camera stamps frames with CLOCK_MONOTONIC,
+// which all uvcvideo ones do.
+pub struct CameraGroup {
+	tasks: Vec<JoinHandle<io::Result<Camera>>>,
+	frames: Receiver<Message>,
+	pending: Vec<VecDeque<GroupFrame>>,
+	tolerance: Duration,
+	stats: Vec<StreamStats>,
+	skew: Vec<SkewStats>,
+}
+
+impl CameraGroup {
+	// `tolerance` is how far apart frames can be and still count as the same moment, about
+	// half a frame interval for cameras running at the same rate
+	pub fn new(cameras: Vec<Camera>, tolerance: Duration) -> Self {
+		let count = cameras.len();
+		let (tx, frames) = mpsc::channel(4 * count.max(1));
+		let tasks = cameras
+			.into_iter()
+			.enumerate()
+			.map(|(index, cam)| tokio::spawn(stream_camera(index, cam, tx.clone())))
+			.collect();
+		Self {
+			tasks,
+			frames,
+			pending: vec![VecDeque::new(); count],
+			tolerance,
+			stats: vec![StreamStats::default(); count],
+			skew: vec![SkewStats::default(); count],
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.pending.len()
+	}
+
+	pub fn stats(&self) -> &[StreamStats] {
+		&self.stats
+	}
+
+	pub fn skew(&self) -> &[SkewStats] {
+		&self.skew
+	}
+
+	pub async fn next(&mut self) -> Result<FrameSet, CameraError> {
+		loop {
+			if let Some(set) = self.try_match() {
+				return Ok(set);
+			}
+			let (index, frame) = self.frames.recv().await.ok_or_else(|| {
+				CameraError::Io(io::Error::new(
+					io::ErrorKind::BrokenPipe,
+					"Every camera in the group stopped",
+				))
+			})?;
+			let (data, info) = frame?;
+			let dropped = self.stats[index].record(&info);
+			self.pending[index].push_back(GroupFrame {
+				data,
+				info,
+				dropped,
+			});
+		}
+	}
+
+	// Stops every stream and hands the cameras back, in the order they were given
+	pub async fn stop(mut self) -> io::Result<Vec<Camera>> {
+		// the tasks notice the channel closing the next time they send a frame
+		self.frames.close();
+		let mut cameras = Vec::with_capacity(self.tasks.len());
+		for task in self.tasks.drain(..) {
+			cameras.push(task.await??);
+		}
+		Ok(cameras)
+	}
+
+	fn try_match(&mut self) -> Option<FrameSet> {
+		loop {
+			if self.pending.is_empty() || self.pending.iter().any(VecDeque::is_empty) {
+				return None;
+			}
+			let latest = self.heads().max()?;
+			// move every camera to whichever of its queued frames is nearest the latest
+			for (queue, skew) in self.pending.iter_mut().zip(&mut self.skew) {
+				while queue.len() > 1
+					&& queue[1].info.timestamp.abs_diff(latest)
+						<= queue[0].info.timestamp.abs_diff(latest)
+				{
+					queue.pop_front();
+					skew.unmatched += 1;
+				}
+			}
+			let earliest = self.heads().min()?;
+			let latest = self.heads().max()?;
+			if latest - earliest <= self.tolerance {
+				let frames: Vec<GroupFrame> = self
+					.pending
+					.iter_mut()
+					.map(|queue| queue.pop_front().expect("checked not empty"))
+					.collect();
+				let reference = frames[0].info.timestamp.as_secs_f64();
+				for (frame, skew) in frames.iter().zip(&mut self.skew) {
+					skew.record(frame.info.timestamp.as_secs_f64() - reference);
+				}
+				return Some(FrameSet {
+					frames,
+					skew: latest - earliest,
+				});
+			}
+			// every frame the latest camera sends from now on is later still, so the
+			// earliest frame can't be matched any more
+			let (index, _) = self
+				.heads()
+				.enumerate()
+				.min_by_key(|&(_, timestamp)| timestamp)?;
+			self.pending[index].pop_front();
+			self.skew[index].unmatched += 1;
+		}
+	}
+
+	fn heads(&self) -> impl Iterator<Item = Duration> + '_ {
+		self.pending
+			.iter()
+			.filter_map(|queue| queue.front())
+			.map(|frame| frame.info.timestamp)
+	}
+}
+
+impl Drop for CameraGroup {
+	fn drop(&mut self) {
+		// dropped without `stop`, the cameras go down with their tasks
+		for task in &self.tasks {
+			task.abort();
+		}
+	}
+}
+
+// Errors go to the group along with the frames, the task ends after sending one
+async fn stream_camera(
+	index: usize,
+	mut cam: Camera,
+	frames: Sender<Message>,
+) -> io::Result<Camera> {
+	if let Err(e) = cam.start() {
+		let _ = frames.send((index, Err(cam.device_error(e)))).await;
+		return Ok(cam);
+	}
+	loop {
+		let frame = cam.next_frame(|data, info| (data.to_vec(), info)).await;
+		let failed = frame.is_err();
+		// the channel closes when the group stops
+		if frames.send((index, frame)).await.is_err() || failed {
+			break;
+		}
+	}
+	cam.stop()?;
+	Ok(cam)
+}
@@ -0,0 +1,96 @@
+use std::{
+	ops::Deref,
+	pin::Pin,
+	sync::Arc,
+	task::{ready, Context, Poll},
+};
+
+use futures::Stream;
+
+use super::{
+	frame::{FrameInfo, StreamStats},
+	internal::FrameBufferPool,
+	Camera, CameraError,
+};
+
+// A dequeued frame that owns its buffer until dropped, so it can be decoded on another
+// task while the camera keeps streaming into the rest of the pool.
+pub struct FrameGuard {
+	buffers: Arc<FrameBufferPool>,
+	index: usize,
+	pub info: FrameInfo,
+	// frames the driver skipped between the previous frame and this one
+	pub dropped: u32,
+}
+
+impl FrameGuard {
+	pub fn data(&self) -> &[u8] {
+		self.buffers.data(self.index, self.info.bytesused as usize)
+	}
+}
+
+impl Deref for FrameGuard {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		self.data()
+	}
+}
+
+impl Drop for FrameGuard {
+	fn drop(&mut self) {
+		// if this fails the device is most likely gone, which the stream reports on its
+		// next frame
+		let _ = self.buffers.requeue(self.index);
+	}
+}
+
+// See `Camera::frames`. Ends after yielding an error for a disconnect.
+pub struct Frames<'cam> {
+	cam: &'cam mut Camera,
+	stats: StreamStats,
+}
+
+impl<'cam> Frames<'cam> {
+	pub(super) fn new(cam: &'cam mut Camera) -> Self {
+		Self {
+			cam,
+			stats: StreamStats::default(),
+		}
+	}
+
+	pub fn stats(&self) -> StreamStats {
+		self.stats
+	}
+
+	pub fn stop(self) -> std::io::Result<()> {
+		self.cam.stop()
+	}
+}
+
+impl Stream for Frames<'_> {
+	type Item = Result<FrameGuard, CameraError>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let this = self.get_mut();
+		if this.cam.disconnected {
+			return Poll::Ready(None);
+		}
+		let Some(buffers) = this.cam.buffers.clone() else {
+			return Poll::Ready(None);
+		};
+		let frame = match ready!(buffers.poll_dequeue(cx)) {
+			Ok((index, info)) => {
+				let dropped = this.stats.record(&info);
+				Ok(FrameGuard {
+					buffers,
+					index,
+					info,
+					dropped,
+				})
+			}
+			Err(e) => Err(this.cam.device_error(e)),
+		};
+		Poll::Ready(Some(frame))
+	}
+}
@@ -1,27 +1,42 @@
 use std::{
+	alloc::{alloc_zeroed, dealloc, Layout},
 	ffi::c_void,
+	future::poll_fn,
 	io, mem,
 	num::NonZeroUsize,
-	os::fd::{AsFd, AsRawFd, OwnedFd},
+	os::{
+		fd::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+		raw::c_ulong,
+	},
 	ptr::NonNull,
 	slice,
+	sync::{Arc, Mutex, MutexGuard, PoisonError},
+	task::{ready, Context, Poll, Waker},
 };
 
 use nix::{
 	errno::Errno,
-	ioctl_read_bad, ioctl_write_ptr_bad,
+	fcntl::OFlag,
+	ioctl_read_bad, ioctl_readwrite_bad, ioctl_write_ptr_bad,
 	sys::mman::{mmap, munmap, MapFlags, ProtFlags},
+	unistd::{sysconf, SysconfVar},
 };
 use tokio::io::unix::AsyncFd;
 use v4l2_sys::{
-	v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE, v4l2_buffer, v4l2_control,
-	v4l2_field_V4L2_FIELD_NONE, v4l2_format, v4l2_memory_V4L2_MEMORY_MMAP, v4l2_requestbuffers,
-	V4L2_CID_BACKLIGHT_COMPENSATION, V4L2_CID_BRIGHTNESS, V4L2_CID_CONTRAST,
-	V4L2_CID_EXPOSURE_ABSOLUTE, V4L2_CID_EXPOSURE_AUTO, V4L2_CID_GAIN, V4L2_CID_GAMMA,
-	V4L2_CID_HUE, V4L2_CID_SATURATION, V4L2_CID_WHITE_BALANCE_TEMPERATURE, VIDIOC_DQBUF,
-	VIDIOC_QBUF, VIDIOC_QUERYBUF, VIDIOC_REQBUFS, VIDIOC_STREAMON, VIDIOC_S_CTRL, VIDIOC_S_FMT,
+	v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE, v4l2_buffer, v4l2_control, v4l2_exportbuffer,
+	v4l2_ext_control, v4l2_ext_controls, v4l2_field_V4L2_FIELD_NONE, v4l2_format,
+	v4l2_memory_V4L2_MEMORY_MMAP, v4l2_memory_V4L2_MEMORY_USERPTR, v4l2_requestbuffers,
+	V4L2_CID_AUTO_WHITE_BALANCE, V4L2_CID_BACKLIGHT_COMPENSATION, V4L2_CID_BRIGHTNESS,
+	V4L2_CID_CONTRAST, V4L2_CID_EXPOSURE_ABSOLUTE, V4L2_CID_EXPOSURE_AUTO, V4L2_CID_FOCUS_ABSOLUTE,
+	V4L2_CID_FOCUS_AUTO, V4L2_CID_FOCUS_RELATIVE, V4L2_CID_GAIN, V4L2_CID_GAMMA, V4L2_CID_HUE,
+	V4L2_CID_SATURATION, V4L2_CID_WHITE_BALANCE_TEMPERATURE, V4L2_CTRL_WHICH_CUR_VAL, VIDIOC_DQBUF,
+	VIDIOC_EXPBUF, VIDIOC_G_CTRL, VIDIOC_G_EXT_CTRLS, VIDIOC_G_FMT, VIDIOC_QBUF, VIDIOC_QUERYBUF,
+	VIDIOC_REQBUFS, VIDIOC_STREAMOFF, VIDIOC_STREAMON, VIDIOC_S_CTRL, VIDIOC_S_EXT_CTRLS,
+	VIDIOC_S_FMT,
 };
 
+use super::{format::fourcc, frame::FrameInfo};
+
 pub const EXPOSURE: u32 = V4L2_CID_EXPOSURE_ABSOLUTE;
 pub const EXPOSURE_AUTO: u32 = V4L2_CID_EXPOSURE_AUTO;
 pub const GAIN: u32 = V4L2_CID_GAIN;
@@ -32,9 +47,13 @@ pub const HUE: u32 = V4L2_CID_HUE;
 pub const SATURATION: u32 = V4L2_CID_SATURATION;
 pub const WHITE_BALANCE: u32 = V4L2_CID_WHITE_BALANCE_TEMPERATURE;
 pub const BLACKLIGHT_COMPENSATION: u32 = V4L2_CID_BACKLIGHT_COMPENSATION;
-pub const WHITE_BALANCE_AUTO: u32 = V4L2_CID_WHITE_BALANCE_TEMPERATURE;
+pub const WHITE_BALANCE_AUTO: u32 = V4L2_CID_AUTO_WHITE_BALANCE;
+pub const FOCUS_ABSOLUTE: u32 = V4L2_CID_FOCUS_ABSOLUTE;
+// write-only on most cameras, moves by the value given
+pub const FOCUS_RELATIVE: u32 = V4L2_CID_FOCUS_RELATIVE;
+pub const FOCUS_AUTO: u32 = V4L2_CID_FOCUS_AUTO;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct VideoPixelFormat {
 	pub width: u32,
 	pub height: u32,
@@ -44,7 +63,7 @@ pub struct VideoPixelFormat {
 
 pub struct VideoFormat(pub v4l2_format);
 
-pub const MJPEG_FMT: u32 = u32::from_ne_bytes(*b"MJPG").swap_bytes();
+pub const MJPEG_FMT: u32 = fourcc(b"MJPG");
 
 impl VideoFormat {
 	pub fn new() -> Self {
@@ -75,9 +94,35 @@ impl VideoFormat {
 		self
 	}
 
-	pub fn apply(&self, dev: &impl AsRawFd) {
-		unsafe {
-			set_v4l2_format(dev.as_raw_fd(), &self.0).expect("Failed applying v4l2 format");
+	// S_FMT is allowed to adjust the format to the closest one the driver supports, and
+	// writes back what it picked
+	pub fn apply(&mut self, dev: &impl AsRawFd) -> io::Result<()> {
+		unsafe { set_v4l2_format(dev.as_raw_fd(), &mut self.0)? };
+		Ok(())
+	}
+
+	pub fn read(dev: &impl AsRawFd) -> io::Result<Self> {
+		let mut fmt = Self::new().set_video_capture_type();
+		unsafe { get_v4l2_format(dev.as_raw_fd(), &mut fmt.0)? };
+		Ok(fmt)
+	}
+
+	// Row length in bytes including padding, 0 for compressed formats
+	pub fn bytes_per_line(&self) -> u32 {
+		unsafe { self.0.fmt.pix.bytesperline }
+	}
+
+	// bytes a buffer needs for one frame, the most a compressed frame can take for MJPEG
+	pub fn size_image(&self) -> u32 {
+		unsafe { self.0.fmt.pix.sizeimage }
+	}
+
+	pub fn pix_format(&self) -> VideoPixelFormat {
+		let pix = unsafe { self.0.fmt.pix };
+		VideoPixelFormat {
+			width: pix.width,
+			height: pix.height,
+			format: pix.pixelformat,
 		}
 	}
 }
@@ -88,103 +133,240 @@ impl Default for VideoFormat {
 	}
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BufferMemory {
+	// allocated by the driver and mapped in, can be exported as dmabuf fds
+	#[default]
+	Mmap,
+	// page aligned memory we allocate and the driver fills in place, not every driver
+	// supports it (REQBUFS fails with EINVAL)
+	UserPtr,
+}
+
+impl BufferMemory {
+	fn raw(self) -> u32 {
+		match self {
+			Self::Mmap => v4l2_memory_V4L2_MEMORY_MMAP,
+			Self::UserPtr => v4l2_memory_V4L2_MEMORY_USERPTR,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BufferState {
+	// ours and unused, STREAMOFF leaves every buffer that wasn't held here
+	Idle,
+	// with the driver, waiting to be filled
+	Queued,
+	// dequeued and being read, by `with_frame` or a `FrameGuard`
+	Held,
+}
+
+struct QueueState {
+	streaming: bool,
+	buffers: Vec<BufferState>,
+	// a stream waiting for a held buffer to come back, see `poll_dequeue`
+	waker: Option<Waker>,
+}
+
+// Shared between the camera and any `FrameGuard`s, which requeue their buffer from
+// whichever task drops them. The lock keeps that from racing STREAMON/STREAMOFF.
 pub struct FrameBufferPool {
+	dev: Arc<AsyncFd<OwnedFd>>,
 	pool: Box<[FrameBuffer]>,
-	idx: u32,
+	memory: BufferMemory,
+	state: Mutex<QueueState>,
 }
 
 struct FrameBuffer {
 	data: *mut u8,
 	length: usize,
+	// only for USERPTR buffers, which are ours to free
+	layout: Option<Layout>,
 }
 
+// SAFETY: the pointers are to memory only this pool owns, mapped or allocated. The driver
+// only writes to a buffer while it's queued, and it's only read while held, so sharing
+// them between threads is fine.
+unsafe impl Send for FrameBuffer {}
+unsafe impl Sync for FrameBuffer {}
+
 impl FrameBufferPool {
-	pub fn new(dev: &impl AsFd, count: u32) -> io::Result<Self> {
-		let mut req: v4l2_requestbuffers = unsafe { mem::zeroed() };
-		req.count = count;
-		req.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
-		req.memory = v4l2_memory_V4L2_MEMORY_MMAP;
-		unsafe {
-			set_v4l2_reqbufs(dev.as_fd().as_raw_fd(), &req)
-				.expect("Failed setting request buffers");
+	// The driver can hand out a different number of buffers than asked for (uvcvideo has a
+	// minimum), the pool is however many it actually allocated.
+	pub fn new(dev: &Arc<AsyncFd<OwnedFd>>, count: u32, memory: BufferMemory) -> io::Result<Self> {
+		let count = request_buffers(dev, count, memory)?;
+		if count == 0 {
+			return Err(io::Error::new(
+				io::ErrorKind::OutOfMemory,
+				"The driver didn't allocate any buffers",
+			));
 		}
-		let pool = (0..count)
-			.map(|idx| FrameBuffer::create(dev, idx))
-			.collect::<io::Result<_>>()?;
+		let pool: Box<[FrameBuffer]> = match memory {
+			BufferMemory::Mmap => (0..count)
+				.map(|idx| FrameBuffer::map(dev, idx))
+				.collect::<io::Result<_>>()?,
+			BufferMemory::UserPtr => {
+				let size = VideoFormat::read(dev)?.size_image() as usize;
+				(0..count)
+					.map(|_| FrameBuffer::alloc(size))
+					.collect::<io::Result<_>>()?
+			}
+		};
+		let state = Mutex::new(QueueState {
+			streaming: false,
+			buffers: vec![BufferState::Idle; pool.len()],
+			waker: None,
+		});
 
-		Ok(Self { pool, idx: 0 })
+		Ok(Self {
+			dev: dev.clone(),
+			pool,
+			memory,
+			state,
+		})
 	}
 
 	pub fn len(&self) -> usize {
 		self.pool.len()
 	}
 
-	pub(super) async fn capture(&mut self, dev: &mut AsyncFd<OwnedFd>) -> io::Result<&[u8]> {
-		let len = self.pool.len() as u32;
-		let frame = &mut self.pool[self.idx as usize];
-		let image = frame.capture(self.idx, dev).await?;
-		self.idx = (self.idx + 1) % len;
-		Ok(image)
+	pub fn memory(&self) -> BufferMemory {
+		self.memory
 	}
 
-	pub(super) fn enqueue_all(&mut self, dev: &mut AsyncFd<OwnedFd>) -> io::Result<()> {
-		for (idx, frame) in self.pool.iter_mut().enumerate() {
-			frame.enqueue(idx, dev)?;
+	// REQBUFS(0) fails with EBUSY while any MMAP buffers are still mapped, so they're
+	// unmapped first. USERPTR buffers are the other way around, the driver has to let go
+	// of them before they're freed.
+	pub(super) fn release(self) -> io::Result<()> {
+		let memory = self.memory;
+		let dev = self.dev.clone();
+		match memory {
+			BufferMemory::Mmap => {
+				drop(self);
+				request_buffers(&dev, 0, memory)?;
+			}
+			BufferMemory::UserPtr => {
+				request_buffers(&dev, 0, memory)?;
+				drop(self);
+			}
 		}
 		Ok(())
 	}
 
-	pub(super) fn enqueue(&mut self, idx: usize, dev: &mut AsyncFd<OwnedFd>) -> io::Result<()> {
-		self.pool[idx].enqueue(idx, dev)
+	// A dmabuf fd for buffer `idx`, which stays valid (and keeps the memory alive) after the
+	// pool is released. Only MMAP buffers can be exported.
+	pub(super) fn export(&self, idx: usize) -> io::Result<OwnedFd> {
+		if self.memory != BufferMemory::Mmap {
+			return Err(io::Error::new(
+				io::ErrorKind::Unsupported,
+				"Only MMAP buffers can be exported",
+			));
+		}
+		let mut exp: v4l2_exportbuffer = unsafe { mem::zeroed() };
+		exp.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
+		exp.index = idx as u32;
+		exp.flags = (OFlag::O_RDONLY | OFlag::O_CLOEXEC).bits() as u32;
+		unsafe { v4l2_export_buf(self.dev.as_raw_fd(), &mut exp)? };
+		// SAFETY: EXPBUF succeeded, so fd is a new dmabuf fd that nothing else owns
+		Ok(unsafe { OwnedFd::from_raw_fd(exp.fd) })
 	}
 
-	pub(super) async fn dequeue(
-		&mut self,
-		dev: &mut AsyncFd<OwnedFd>,
-	) -> io::Result<(&[u8], usize)> {
-		let buf = loop {
-			let mut guard = dev.readable().await?;
-			let mut buf: v4l2_buffer = unsafe { mem::zeroed() };
-			buf.memory = v4l2_memory_V4L2_MEMORY_MMAP;
-			buf.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
-			let ret = unsafe { v4l2_dequeue(dev.as_raw_fd(), &mut buf) };
-			match ret {
-				Ok(_) => break buf,
-				Err(Errno::EAGAIN) => {
-					guard.clear_ready();
-					continue;
-				}
-				Err(e) => return Err(io::Error::from_raw_os_error(e as i32)),
+	// Queues every buffer that isn't held and turns the stream on. Held buffers are queued
+	// when they're given back.
+	pub(super) fn start(&self) -> io::Result<()> {
+		let mut state = self.lock();
+		for (idx, buffer) in self.pool.iter().enumerate() {
+			if state.buffers[idx] == BufferState::Idle {
+				buffer.enqueue(idx, self.memory, &self.dev)?;
+				state.buffers[idx] = BufferState::Queued;
 			}
-		};
-		let active_frame = &mut self.pool[buf.index as usize];
-		// previous bug, don't enqueue before frame has been processed
-		// or you get a data race
-		// active_frame.enqueue(buf.index, dev)?;
+		}
+		enable_video_stream(&self.dev)?;
+		state.streaming = true;
+		Ok(())
+	}
 
-		unsafe {
-			Ok((
-				slice::from_raw_parts(active_frame.data, buf.bytesused as usize),
-				buf.index as usize,
-			))
+	// STREAMOFF takes back every queued buffer without waiting for it to be filled
+	pub(super) fn stop(&self) -> io::Result<()> {
+		let mut state = self.lock();
+		disable_video_stream(&self.dev)?;
+		state.streaming = false;
+		for buffer in &mut state.buffers {
+			if *buffer == BufferState::Queued {
+				*buffer = BufferState::Idle;
+			}
 		}
+		Ok(())
 	}
 
-	pub(super) async fn dequeue_all(&mut self, dev: &mut AsyncFd<OwnedFd>) -> io::Result<()> {
-		for _ in 0..self.len() {
-			self.dequeue(dev).await?;
+	// Gives back a held buffer, straight to the driver if it's streaming
+	pub(super) fn requeue(&self, idx: usize) -> io::Result<()> {
+		let mut state = self.lock();
+		if state.streaming {
+			self.pool[idx].enqueue(idx, self.memory, &self.dev)?;
+			state.buffers[idx] = BufferState::Queued;
+		} else {
+			state.buffers[idx] = BufferState::Idle;
+		}
+		if let Some(waker) = state.waker.take() {
+			waker.wake();
 		}
 		Ok(())
 	}
+
+	pub(super) async fn dequeue(&self) -> io::Result<(usize, FrameInfo)> {
+		poll_fn(|cx| self.poll_dequeue(cx)).await
+	}
+
+	// The dequeued buffer is held until `requeue`. With every buffer held there's nothing
+	// for the driver to fill, so this waits for one to be given back rather than polling
+	// the device.
+	pub(super) fn poll_dequeue(&self, cx: &mut Context) -> Poll<io::Result<(usize, FrameInfo)>> {
+		loop {
+			{
+				let mut state = self.lock();
+				if !state.buffers.contains(&BufferState::Queued) {
+					state.waker = Some(cx.waker().clone());
+					return Poll::Pending;
+				}
+			}
+			let mut guard = ready!(self.dev.poll_read_ready(cx))?;
+			let mut buf: v4l2_buffer = unsafe { mem::zeroed() };
+			buf.memory = self.memory.raw();
+			buf.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
+			match unsafe { v4l2_dequeue(self.dev.as_raw_fd(), &mut buf) } {
+				Ok(_) => {
+					// previous bug, don't enqueue before frame has been processed
+					// or you get a data race
+					self.lock().buffers[buf.index as usize] = BufferState::Held;
+					return Poll::Ready(Ok((buf.index as usize, FrameInfo::from_buffer(&buf))));
+				}
+				Err(Errno::EAGAIN) => guard.clear_ready(),
+				Err(e) => return Poll::Ready(Err(e.into())),
+			}
+		}
+	}
+
+	// Only valid while buffer `idx` is held
+	pub(super) fn data(&self, idx: usize, len: usize) -> &[u8] {
+		let buffer = &self.pool[idx];
+		unsafe { slice::from_raw_parts(buffer.data, len.min(buffer.length)) }
+	}
+
+	fn lock(&self) -> MutexGuard<'_, QueueState> {
+		// nothing in here can be left half updated by a panic
+		self.state.lock().unwrap_or_else(PoisonError::into_inner)
+	}
 }
 
 impl FrameBuffer {
-	fn create(dev: &impl AsFd, index: u32) -> io::Result<Self> {
+	fn map(dev: &impl AsFd, index: u32) -> io::Result<Self> {
 		let mut buf: v4l2_buffer = unsafe { mem::zeroed() };
 		buf.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
 		buf.memory = v4l2_memory_V4L2_MEMORY_MMAP;
 		buf.index = index;
-		unsafe { create_v4l2_buf(dev.as_fd().as_raw_fd(), &buf)? };
+		unsafe { query_v4l2_buf(dev.as_fd().as_raw_fd(), &mut buf)? };
 		let length = buf.length as usize;
 		let data = unsafe {
 			mmap(
@@ -194,53 +376,54 @@ impl FrameBuffer {
 				MapFlags::MAP_SHARED,
 				dev,
 				buf.m.offset.into(),
-			)
-			.expect("Failed mmap for buffer")
+			)?
 			.as_ptr() as *mut u8
 		};
-		Ok(Self { length, data })
+		Ok(Self {
+			length,
+			data,
+			layout: None,
+		})
 	}
 
-	fn enqueue(&mut self, idx: usize, dev: &mut AsyncFd<OwnedFd>) -> io::Result<()> {
-		let mut buf: v4l2_buffer = unsafe { mem::zeroed() };
-		buf.index = idx as u32;
-		buf.memory = v4l2_memory_V4L2_MEMORY_MMAP;
-		buf.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
-		unsafe {
-			v4l2_queue(dev.as_raw_fd(), &buf)?;
+	// Rounded up to whole pages, some drivers (anything using dma-contig) need them aligned
+	fn alloc(size: usize) -> io::Result<Self> {
+		let page = sysconf(SysconfVar::PAGE_SIZE)?.unwrap_or(4096) as usize;
+		let layout = Layout::from_size_align(size.next_multiple_of(page), page)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+		let data = unsafe { alloc_zeroed(layout) };
+		if data.is_null() {
+			return Err(io::ErrorKind::OutOfMemory.into());
 		}
-		Ok(())
+		Ok(Self {
+			length: layout.size(),
+			data,
+			layout: Some(layout),
+		})
 	}
 
-	async fn capture(&mut self, idx: u32, dev: &mut AsyncFd<OwnedFd>) -> io::Result<&[u8]> {
+	fn enqueue(&self, idx: usize, memory: BufferMemory, dev: &impl AsRawFd) -> io::Result<()> {
 		let mut buf: v4l2_buffer = unsafe { mem::zeroed() };
-		buf.index = idx;
-		buf.memory = v4l2_memory_V4L2_MEMORY_MMAP;
+		buf.index = idx as u32;
+		buf.memory = memory.raw();
 		buf.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
+		if memory == BufferMemory::UserPtr {
+			buf.m.userptr = self.data as c_ulong;
+			buf.length = self.length as u32;
+		}
 		unsafe {
 			v4l2_queue(dev.as_raw_fd(), &buf)?;
 		}
-		let buf = loop {
-			let mut guard = dev.readable().await?;
-			let mut buf: v4l2_buffer = unsafe { mem::zeroed() };
-			buf.memory = v4l2_memory_V4L2_MEMORY_MMAP;
-			buf.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
-			let ret = unsafe { v4l2_dequeue(dev.as_raw_fd(), &mut buf) };
-			match ret {
-				Ok(_) => break buf,
-				Err(Errno::EAGAIN) => {
-					guard.clear_ready();
-					continue;
-				}
-				Err(e) => return Err(io::Error::from_raw_os_error(e as i32)),
-			}
-		};
-		unsafe { Ok(slice::from_raw_parts(self.data, buf.bytesused as usize)) }
+		Ok(())
 	}
 }
 
 impl Drop for FrameBuffer {
 	fn drop(&mut self) {
+		if let Some(layout) = self.layout {
+			unsafe { dealloc(self.data, layout) };
+			return;
+		}
 		let res = unsafe {
 			munmap(
 				NonNull::<c_void>::new_unchecked(self.data as *mut c_void),
@@ -253,36 +436,113 @@ impl Drop for FrameBuffer {
 	}
 }
 
-pub fn enable_video_stream(dev: &impl AsRawFd) {
-	unsafe {
-		let ty = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
-		enable_v4l2_stream(dev.as_raw_fd(), &ty).expect("Failed turning on stream");
-	}
+// Returns how many buffers the driver allocated, 0 frees them all
+fn request_buffers(dev: &impl AsFd, count: u32, memory: BufferMemory) -> io::Result<u32> {
+	let mut req: v4l2_requestbuffers = unsafe { mem::zeroed() };
+	req.count = count;
+	req.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
+	req.memory = memory.raw();
+	unsafe { v4l2_reqbufs(dev.as_fd().as_raw_fd(), &mut req)? };
+	Ok(req.count)
 }
 
-pub fn set_dev_settings(dev: &impl AsRawFd, setting: u32, value: i32) {
-	let c: v4l2_control = v4l2_control { id: setting, value };
-	unsafe {
-		v4l2_set_ctrl(dev.as_raw_fd(), &c).expect("Failed updating settings");
-	}
+fn enable_video_stream(dev: &impl AsRawFd) -> io::Result<()> {
+	let ty = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
+	unsafe { enable_v4l2_stream(dev.as_raw_fd(), &ty)? };
+	Ok(())
+}
+
+fn disable_video_stream(dev: &impl AsRawFd) -> io::Result<()> {
+	let ty = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
+	unsafe { disable_v4l2_stream(dev.as_raw_fd(), &ty)? };
+	Ok(())
+}
+
+pub fn set_dev_settings(dev: &impl AsRawFd, setting: u32, value: i32) -> io::Result<()> {
+	let mut c: v4l2_control = v4l2_control { id: setting, value };
+	unsafe { v4l2_set_ctrl(dev.as_raw_fd(), &mut c)? };
+	Ok(())
 }
 
-pub fn get_dev_settings(dev: &impl AsRawFd, setting: u32) -> i32 {
+pub fn get_dev_settings(dev: &impl AsRawFd, setting: u32) -> io::Result<i32> {
 	let mut c: v4l2_control = v4l2_control {
 		id: setting,
 		value: 0,
 	};
-	unsafe {
-		v4l2_get_ctrl(dev.as_raw_fd(), &mut c).expect("Failed getting settings");
+	unsafe { v4l2_get_ctrl(dev.as_raw_fd(), &mut c)? };
+	Ok(c.value)
+}
+
+// All or nothing, the driver checks every value before applying any of them
+pub fn set_dev_ext_settings(dev: &impl AsRawFd, settings: &[(u32, i32)]) -> io::Result<()> {
+	let mut controls: Vec<v4l2_ext_control> = settings
+		.iter()
+		.map(|&(id, value)| {
+			let mut c: v4l2_ext_control = unsafe { mem::zeroed() };
+			c.id = id;
+			c.__bindgen_anon_1.value = value;
+			c
+		})
+		.collect();
+	ext_controls(dev, &mut controls, true)
+}
+
+pub fn get_dev_ext_settings(dev: &impl AsRawFd, ids: &[u32]) -> io::Result<Vec<i32>> {
+	let mut controls: Vec<v4l2_ext_control> = ids
+		.iter()
+		.map(|&id| {
+			let mut c: v4l2_ext_control = unsafe { mem::zeroed() };
+			c.id = id;
+			c
+		})
+		.collect();
+	ext_controls(dev, &mut controls, false)?;
+	Ok(controls
+		.iter()
+		.map(|c| unsafe { c.__bindgen_anon_1.value })
+		.collect())
+}
+
+fn ext_controls(
+	dev: &impl AsRawFd,
+	controls: &mut [v4l2_ext_control],
+	set: bool,
+) -> io::Result<()> {
+	let mut ext: v4l2_ext_controls = unsafe { mem::zeroed() };
+	ext.__bindgen_anon_1.which = V4L2_CTRL_WHICH_CUR_VAL;
+	ext.count = controls.len() as u32;
+	ext.controls = controls.as_mut_ptr();
+	let res = unsafe {
+		if set {
+			v4l2_set_ext_ctrls(dev.as_raw_fd(), &mut ext)
+		} else {
+			v4l2_get_ext_ctrls(dev.as_raw_fd(), &mut ext)
+		}
+	};
+	match res {
+		Ok(_) => Ok(()),
+		// error_idx == count means it failed before getting to any single control
+		Err(e) if (ext.error_idx as usize) < controls.len() => {
+			let id = controls[ext.error_idx as usize].id;
+			Err(io::Error::new(
+				io::Error::from(e).kind(),
+				format!("Control {id:#x}: {e}"),
+			))
+		}
+		Err(e) => Err(e.into()),
 	}
-	c.value
 }
 
-ioctl_write_ptr_bad!(set_v4l2_format, VIDIOC_S_FMT, v4l2_format);
-ioctl_write_ptr_bad!(set_v4l2_reqbufs, VIDIOC_REQBUFS, v4l2_requestbuffers);
-ioctl_write_ptr_bad!(create_v4l2_buf, VIDIOC_QUERYBUF, v4l2_buffer);
+ioctl_readwrite_bad!(set_v4l2_format, VIDIOC_S_FMT, v4l2_format);
+ioctl_readwrite_bad!(get_v4l2_format, VIDIOC_G_FMT, v4l2_format);
+ioctl_readwrite_bad!(v4l2_reqbufs, VIDIOC_REQBUFS, v4l2_requestbuffers);
+ioctl_readwrite_bad!(query_v4l2_buf, VIDIOC_QUERYBUF, v4l2_buffer);
 ioctl_write_ptr_bad!(enable_v4l2_stream, VIDIOC_STREAMON, u32);
+ioctl_write_ptr_bad!(disable_v4l2_stream, VIDIOC_STREAMOFF, u32);
 ioctl_write_ptr_bad!(v4l2_queue, VIDIOC_QBUF, v4l2_buffer);
 ioctl_read_bad!(v4l2_dequeue, VIDIOC_DQBUF, v4l2_buffer);
-ioctl_write_ptr_bad!(v4l2_set_ctrl, VIDIOC_S_CTRL, v4l2_control);
-ioctl_read_bad!(v4l2_get_ctrl, VIDIOC_S_CTRL, v4l2_control);
+ioctl_readwrite_bad!(v4l2_export_buf, VIDIOC_EXPBUF, v4l2_exportbuffer);
+ioctl_readwrite_bad!(v4l2_set_ctrl, VIDIOC_S_CTRL, v4l2_control);
+ioctl_readwrite_bad!(v4l2_get_ctrl, VIDIOC_G_CTRL, v4l2_control);
+ioctl_readwrite_bad!(v4l2_set_ext_ctrls, VIDIOC_S_EXT_CTRLS, v4l2_ext_controls);
+ioctl_readwrite_bad!(v4l2_get_ext_ctrls, VIDIOC_G_EXT_CTRLS, v4l2_ext_controls);
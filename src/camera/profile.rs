@@ -0,0 +1,230 @@
+use std::{
+	collections::BTreeMap,
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
+use v4l2_sys::{
+	V4L2_CID_AUTOBRIGHTNESS, V4L2_CID_AUTOGAIN, V4L2_CID_AUTO_WHITE_BALANCE,
+	V4L2_CID_EXPOSURE_AUTO, V4L2_CID_FOCUS_AUTO, V4L2_CID_HUE_AUTO,
+};
+
+use super::{
+	controls::{ControlType, ControlValue},
+	device::DeviceInfo,
+	Camera,
+};
+
+// Controls that switch others between the driver and the user
+const AUTO_CONTROLS: [u32; 6] = [
+	V4L2_CID_EXPOSURE_AUTO,
+	V4L2_CID_AUTO_WHITE_BALANCE,
+	V4L2_CID_FOCUS_AUTO,
+	V4L2_CID_HUE_AUTO,
+	V4L2_CID_AUTOGAIN,
+	V4L2_CID_AUTOBRIGHTNESS,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileControl {
+	pub id: u32,
+	// only for reading the file, controls are matched by id
+	pub name: String,
+	pub value: i32,
+}
+
+// Every writable control of a camera, in the driver's order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CameraProfile {
+	pub controls: Vec<ProfileControl>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlDiff {
+	pub id: u32,
+	pub name: String,
+	// None if the control isn't in the profile, or is read-only or inactive on the camera
+	pub saved: Option<i32>,
+	pub current: Option<i32>,
+}
+
+impl CameraProfile {
+	// Inactive controls are left out, the driver is setting those itself
+	pub fn capture(cam: &Camera) -> io::Result<Self> {
+		let controls: Vec<_> =
+			cam.controls()?
+				.into_iter()
+				.filter(|c| {
+					matches!(
+						c.kind,
+						ControlType::Integer
+							| ControlType::Boolean
+							| ControlType::Menu | ControlType::IntegerMenu
+					) && !c.is_read_only()
+						&& !c.is_inactive()
+				})
+				.collect();
+		let ids: Vec<u32> = controls.iter().map(|c| c.id).collect();
+		let values = cam.get_many(&ids)?;
+		Ok(Self {
+			controls: controls
+				.into_iter()
+				.zip(values)
+				.map(|(c, value)| ProfileControl {
+					id: c.id,
+					name: c.name,
+					value,
+				})
+				.collect(),
+		})
+	}
+
+	// One control at a time, auto modes first, so switching one off has taken effect before
+	// the manual value behind it is checked and set. The driver's order doesn't do that for
+	// every control, FocusAbsolute comes before FocusAuto. Controls the camera refuses right
+	// now are skipped with a warning rather than failing the rest. Returns what was set.
+	pub fn apply(&self, cam: &mut Camera) -> io::Result<Vec<ControlValue>> {
+		let (auto, manual): (Vec<_>, Vec<_>) = self
+			.values()
+			.into_iter()
+			.partition(|v| AUTO_CONTROLS.contains(&v.id));
+		let mut applied = Vec::with_capacity(self.controls.len());
+		for value in auto.into_iter().chain(manual) {
+			match cam.set_many(&[value]) {
+				Ok(set) => applied.push(ControlValue {
+					id: value.id,
+					value: set[0],
+				}),
+				// EACCES while an auto mode the profile leaves on drives it, EBUSY while
+				// streaming for some
+				Err(e)
+					if matches!(
+						e.raw_os_error().map(Errno::from_raw),
+						Some(Errno::EACCES | Errno::EBUSY)
+					) =>
+				{
+					let name = self.get(value.id).map_or("", |c| c.name.as_str());
+					eprintln!("Not applying {name} ({:#x}): {e}", value.id);
+				}
+				Err(e) => return Err(e),
+			}
+		}
+		Ok(applied)
+	}
+
+	// Only the controls that differ
+	pub fn diff(&self, cam: &Camera) -> io::Result<Vec<ControlDiff>> {
+		let current = Self::capture(cam)?;
+		let mut diffs = Vec::new();
+		for saved in &self.controls {
+			let now = current.get(saved.id);
+			if now.map(|c| c.value) != Some(saved.value) {
+				diffs.push(ControlDiff {
+					id: saved.id,
+					name: saved.name.clone(),
+					saved: Some(saved.value),
+					current: now.map(|c| c.value),
+				});
+			}
+		}
+		for now in &current.controls {
+			if self.get(now.id).is_none() {
+				diffs.push(ControlDiff {
+					id: now.id,
+					name: now.name.clone(),
+					saved: None,
+					current: Some(now.value),
+				});
+			}
+		}
+		Ok(diffs)
+	}
+
+	pub fn get(&self, id: u32) -> Option<&ProfileControl> {
+		self.controls.iter().find(|c| c.id == id)
+	}
+
+	// For `supervisor::CameraConfig::controls`
+	pub fn values(&self) -> Vec<ControlValue> {
+		self.controls
+			.iter()
+			.map(|c| ControlValue {
+				id: c.id,
+				value: c.value,
+			})
+			.collect()
+	}
+}
+
+// Named profiles for each camera, stored as TOML, or JSON if the path ends in .json
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileStore {
+	// by `profile_key`, then profile name
+	pub cameras: BTreeMap<String, BTreeMap<String, CameraProfile>>,
+}
+
+impl ProfileStore {
+	// A missing file is an empty store, so the first save creates it
+	pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+		let path = path.as_ref();
+		let text = match fs::read_to_string(path) {
+			Ok(text) => text,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+			Err(e) => return Err(e),
+		};
+		let store = if is_json(path) {
+			serde_json::from_str(&text).map_err(|e| invalid_data(path, e))?
+		} else {
+			toml::from_str(&text).map_err(|e| invalid_data(path, e))?
+		};
+		Ok(store)
+	}
+
+	pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+		let path = path.as_ref();
+		let text = if is_json(path) {
+			serde_json::to_string_pretty(self).map_err(|e| invalid_data(path, e))?
+		} else {
+			toml::to_string_pretty(self).map_err(|e| invalid_data(path, e))?
+		};
+		// written next to it first so a failed write doesn't lose every other profile
+		let mut tmp = PathBuf::from(path);
+		tmp.as_mut_os_string().push(".tmp");
+		fs::write(&tmp, text)?;
+		fs::rename(tmp, path)
+	}
+
+	pub fn get(&self, info: &DeviceInfo, name: &str) -> Option<&CameraProfile> {
+		self.cameras.get(&profile_key(info))?.get(name)
+	}
+
+	// Replaces any profile with the same name for this camera
+	pub fn insert(&mut self, info: &DeviceInfo, name: impl Into<String>, profile: CameraProfile) {
+		self.cameras
+			.entry(profile_key(info))
+			.or_default()
+			.insert(name.into(), profile);
+	}
+}
+
+// The serial, or for cameras without one `bus:<bus info>` like `DeviceSelector` takes,
+// which only holds while the camera stays in the same port
+pub fn profile_key(info: &DeviceInfo) -> String {
+	match &info.serial {
+		Some(serial) => serial.clone(),
+		None => format!("bus:{}", info.bus_info),
+	}
+}
+
+fn is_json(path: &Path) -> bool {
+	path.extension().is_some_and(|ext| ext == "json")
+}
+
+fn invalid_data(path: &Path, err: impl std::fmt::Display) -> io::Error {
+	io::Error::new(
+		io::ErrorKind::InvalidData,
+		format!("{}: {err}", path.display()),
+	)
+}
@@ -0,0 +1,273 @@
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+use tokio::time::{sleep_until, Instant};
+use zune_jpeg::JpegDecoder;
+
+use crate::{robot::state::monotonic_now, video::Decoder};
+
+use super::{
+	format::{Fraction, YUV444M_FMT},
+	frame::{TimestampClock, TimestampSource},
+	source::FrameSource,
+	CameraError, FrameInfo, VideoPixelFormat, MJPEG_FMT,
+};
+
+enum Frames {
+	// sorted by name, read one at a time as they're replayed
+	Jpegs { paths: Vec<PathBuf>, next: usize },
+	Video { path: PathBuf, decoder: Decoder },
+}
+
+// Stands in for a camera by replaying a directory of JPEGs (as MJPEG frames) or a
+// recording made with `video::Encoder` (as YUV444M frames). Frames come at the frame
+// interval, with CLOCK_MONOTONIC timestamps of when they were due, like a driver's.
+pub struct ReplayCamera {
+	frames: Frames,
+	format: VideoPixelFormat,
+	interval: Fraction,
+	looped: bool,
+	sequence: u32,
+	// when the first frame was due
+	started: Option<(Instant, Duration)>,
+	data: Vec<u8>,
+}
+
+impl ReplayCamera {
+	// A directory is read as JPEGs, anything else as a video. `interval` defaults to the
+	// video's frame rate, or 30fps for JPEGs.
+	pub fn open(path: impl AsRef<Path>, interval: Option<Fraction>) -> io::Result<Self> {
+		let path = path.as_ref();
+		if path.is_dir() {
+			Self::from_jpegs(path, interval.unwrap_or(Fraction::new(1, 30)))
+		} else {
+			Self::from_video(path, interval)
+		}
+	}
+
+	// Every .jpg and .jpeg in `dir`, which all have to be the size of the first
+	pub fn from_jpegs(dir: impl AsRef<Path>, interval: Fraction) -> io::Result<Self> {
+		let dir = dir.as_ref();
+		let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+			.map(|entry| entry.map(|e| e.path()))
+			.collect::<io::Result<_>>()?;
+		paths.retain(|p| {
+			p.extension().is_some_and(|ext| {
+				ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg")
+			})
+		});
+		paths.sort();
+		let Some(first) = paths.first() else {
+			return Err(io::Error::new(
+				io::ErrorKind::NotFound,
+				format!("No JPEGs in {}", dir.display()),
+			));
+		};
+		let data = fs::read(first)?;
+		let mut decoder = JpegDecoder::new(&data);
+		decoder
+			.decode_headers()
+			.map_err(|e| invalid_data(first, format!("{e:?}")))?;
+		let (width, height) = decoder
+			.dimensions()
+			.ok_or_else(|| invalid_data(first, "JPEG without dimensions"))?;
+		Ok(Self::new(
+			Frames::Jpegs { paths, next: 0 },
+			VideoPixelFormat {
+				width: width as u32,
+				height: height as u32,
+				format: MJPEG_FMT,
+			},
+			interval,
+		))
+	}
+
+	pub fn from_video(path: impl AsRef<Path>, interval: Option<Fraction>) -> io::Result<Self> {
+		let path = path.as_ref().to_path_buf();
+		let decoder = Decoder::open(&path)?;
+		let format = VideoPixelFormat {
+			width: decoder.width,
+			height: decoder.height,
+			format: YUV444M_FMT,
+		};
+		let interval = interval.unwrap_or(decoder.frame_interval);
+		Ok(Self::new(Frames::Video { path, decoder }, format, interval))
+	}
+
+	fn new(frames: Frames, format: VideoPixelFormat, interval: Fraction) -> Self {
+		Self {
+			frames,
+			format,
+			interval,
+			looped: false,
+			sequence: 0,
+			started: None,
+			data: Vec::new(),
+		}
+	}
+
+	// Starts over at the end instead of reporting it
+	pub fn looped(mut self, looped: bool) -> Self {
+		self.looped = looped;
+		self
+	}
+
+	// Loads the next frame into `data`, false at the end
+	fn load_next(&mut self) -> io::Result<bool> {
+		match &mut self.frames {
+			Frames::Jpegs { paths, next } => {
+				if *next == paths.len() {
+					if !self.looped {
+						return Ok(false);
+					}
+					*next = 0;
+				}
+				self.data = fs::read(&paths[*next])?;
+				*next += 1;
+				Ok(true)
+			}
+			Frames::Video { path, decoder } => {
+				if decoder.decode(&mut self.data)? {
+					return Ok(true);
+				}
+				if !self.looped {
+					return Ok(false);
+				}
+				*decoder = Decoder::open(path)?;
+				decoder.decode(&mut self.data)
+			}
+		}
+	}
+}
+
+impl FrameSource for ReplayCamera {
+	fn format(&self) -> VideoPixelFormat {
+		self.format
+	}
+
+	fn bytes_per_line(&self) -> u32 {
+		match self.frames {
+			Frames::Jpegs { .. } => 0,
+			Frames::Video { .. } => self.format.width,
+		}
+	}
+
+	fn frame_interval(&self) -> io::Result<Fraction> {
+		Ok(self.interval)
+	}
+
+	// Ends with an UnexpectedEof error unless looped
+	async fn read_frame<F, R>(&mut self, func: F) -> Result<R, CameraError>
+	where
+		F: FnOnce(&[u8], FrameInfo) -> R,
+	{
+		if !self.load_next()? {
+			return Err(CameraError::Io(io::Error::new(
+				io::ErrorKind::UnexpectedEof,
+				"Replay ended",
+			)));
+		}
+		let interval = Duration::from_secs_f64(self.interval.as_f64());
+		let (start, start_timestamp) = *self
+			.started
+			.get_or_insert_with(|| (Instant::now(), monotonic_now()));
+		let due = interval * self.sequence;
+		sleep_until(start + due).await;
+
+		let info = FrameInfo {
+			index: 0,
+			sequence: self.sequence,
+			timestamp: start_timestamp + due,
+			timestamp_clock: TimestampClock::Monotonic,
+			timestamp_source: TimestampSource::EndOfFrame,
+			bytesused: self.data.len() as u32,
+			flags: 0,
+		};
+		self.sequence = self.sequence.wrapping_add(1);
+		Ok(func(&self.data, info))
+	}
+}
+
+fn invalid_data(path: &Path, err: impl std::fmt::Display) -> io::Error {
+	io::Error::new(
+		io::ErrorKind::InvalidData,
+		format!("{}: {err}", path.display()),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::camera::{
+		convert::Yuv444,
+		validate::{decode_frame, CorruptFramePolicy},
+	};
+
+	// Three 16x16 JPEGs of flat grey, 64, 128 and 192, in that order by name
+	fn fixture() -> PathBuf {
+		Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/replay")
+	}
+
+	const LEVELS: [u8; 3] = [64, 128, 192];
+
+	fn assert_level(planes: &Yuv444, level: u8) {
+		assert!(
+			planes.y.iter().all(|&y| y.abs_diff(level) <= 2),
+			"expected luma around {level}, got {:?}",
+			&planes.y[..4]
+		);
+	}
+
+	#[tokio::test]
+	async fn replays_jpegs_at_the_interval() {
+		let interval = Fraction::new(1, 100);
+		let mut replay = ReplayCamera::from_jpegs(fixture(), interval).unwrap();
+		let format = replay.format();
+		assert_eq!((format.width, format.height), (16, 16));
+		assert_eq!(format.format, MJPEG_FMT);
+
+		let mut planes = Yuv444::new(16, 16);
+		let mut infos = Vec::new();
+		for level in LEVELS {
+			let info = decode_frame(&mut replay, &mut planes, CorruptFramePolicy::Fail)
+				.await
+				.unwrap();
+			assert_level(&planes, level);
+			infos.push(info);
+		}
+		for (i, info) in infos.iter().enumerate() {
+			assert_eq!(info.sequence, i as u32);
+			assert_eq!(info.timestamp_clock, TimestampClock::Monotonic);
+		}
+		for pair in infos.windows(2) {
+			assert_eq!(
+				pair[1].timestamp - pair[0].timestamp,
+				Duration::from_millis(10)
+			);
+		}
+
+		match decode_frame(&mut replay, &mut planes, CorruptFramePolicy::Fail).await {
+			Err(CameraError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+			res => panic!("expected the replay to end, got {res:?}"),
+		}
+	}
+
+	#[tokio::test]
+	async fn starts_over_when_looped() {
+		let mut replay = ReplayCamera::from_jpegs(fixture(), Fraction::new(1, 100))
+			.unwrap()
+			.looped(true);
+		let mut planes = Yuv444::new(16, 16);
+		for i in 0..LEVELS.len() * 2 {
+			let info = decode_frame(&mut replay, &mut planes, CorruptFramePolicy::Fail)
+				.await
+				.unwrap();
+			// sequence numbers keep counting, like a camera's
+			assert_eq!(info.sequence, i as u32);
+			assert_level(&planes, LEVELS[i % LEVELS.len()]);
+		}
+	}
+}
@@ -0,0 +1,147 @@
+use std::{io, mem, os::fd::AsRawFd};
+
+use nix::{errno::Errno, ioctl_readwrite_bad};
+use v4l2_sys::{
+	v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE, v4l2_rect, v4l2_selection, V4L2_SEL_TGT_COMPOSE,
+	V4L2_SEL_TGT_COMPOSE_BOUNDS, V4L2_SEL_TGT_COMPOSE_DEFAULT, V4L2_SEL_TGT_CROP,
+	V4L2_SEL_TGT_CROP_BOUNDS, V4L2_SEL_TGT_CROP_DEFAULT, VIDIOC_G_SELECTION, VIDIOC_S_SELECTION,
+};
+
+// In pixels, relative to the sensor for crop targets and to the frame for compose targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+	pub left: i32,
+	pub top: i32,
+	pub width: u32,
+	pub height: u32,
+}
+
+impl Rect {
+	pub fn new(left: i32, top: i32, width: u32, height: u32) -> Self {
+		Self {
+			left,
+			top,
+			width,
+			height,
+		}
+	}
+
+	// `width` x `height` in the middle of a `frame_width` x `frame_height` frame
+	pub fn centred(frame_width: u32, frame_height: u32, width: u32, height: u32) -> Self {
+		Self::new(
+			(frame_width.saturating_sub(width) / 2) as i32,
+			(frame_height.saturating_sub(height) / 2) as i32,
+			width.min(frame_width),
+			height.min(frame_height),
+		)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.width == 0 || self.height == 0
+	}
+
+	pub fn fits_in(&self, width: u32, height: u32) -> bool {
+		self.left >= 0
+			&& self.top >= 0
+			&& self.left as u64 + self.width as u64 <= width as u64
+			&& self.top as u64 + self.height as u64 <= height as u64
+	}
+}
+
+impl From<v4l2_rect> for Rect {
+	fn from(r: v4l2_rect) -> Self {
+		Self::new(r.left, r.top, r.width, r.height)
+	}
+}
+
+impl From<Rect> for v4l2_rect {
+	fn from(r: Rect) -> Self {
+		v4l2_rect {
+			left: r.left,
+			top: r.top,
+			width: r.width,
+			height: r.height,
+		}
+	}
+}
+
+// Crop picks the part of the sensor that's read out, compose where that lands in the
+// frame (scaled if the sizes differ). Bounds are the limits, defaults what's used when
+// nothing has been set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionTarget {
+	Crop,
+	CropDefault,
+	CropBounds,
+	Compose,
+	ComposeDefault,
+	ComposeBounds,
+}
+
+impl SelectionTarget {
+	fn raw(self) -> u32 {
+		match self {
+			Self::Crop => V4L2_SEL_TGT_CROP,
+			Self::CropDefault => V4L2_SEL_TGT_CROP_DEFAULT,
+			Self::CropBounds => V4L2_SEL_TGT_CROP_BOUNDS,
+			Self::Compose => V4L2_SEL_TGT_COMPOSE,
+			Self::ComposeDefault => V4L2_SEL_TGT_COMPOSE_DEFAULT,
+			Self::ComposeBounds => V4L2_SEL_TGT_COMPOSE_BOUNDS,
+		}
+	}
+
+	pub fn is_writable(self) -> bool {
+		matches!(self, Self::Crop | Self::Compose)
+	}
+}
+
+// What `Camera::crop` ended up doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crop {
+	// the driver crops, frames are now this size
+	Sensor(Rect),
+	// the driver can't, so frames are still full size and need cropping to this after
+	// decoding, see `convert::Yuv444::crop`
+	Software(Rect),
+}
+
+// Drivers without selection support (UVC among them) fail with ENOTTY, or EINVAL for
+// targets they don't have
+pub(super) fn is_unsupported(err: &io::Error) -> bool {
+	matches!(
+		err.raw_os_error().map(Errno::from_raw),
+		Some(Errno::ENOTTY | Errno::EINVAL | Errno::ENODATA)
+	)
+}
+
+pub(super) fn selection(dev: &impl AsRawFd, target: SelectionTarget) -> io::Result<Rect> {
+	let mut sel: v4l2_selection = unsafe { mem::zeroed() };
+	sel.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
+	sel.target = target.raw();
+	unsafe { v4l2_get_selection(dev.as_raw_fd(), &mut sel)? };
+	Ok(sel.r.into())
+}
+
+// Returns the rectangle the driver actually used, which it rounds to what the hardware
+// can do
+pub(super) fn set_selection(
+	dev: &impl AsRawFd,
+	target: SelectionTarget,
+	rect: Rect,
+) -> io::Result<Rect> {
+	if !target.is_writable() {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("{target:?} is read-only"),
+		));
+	}
+	let mut sel: v4l2_selection = unsafe { mem::zeroed() };
+	sel.type_ = v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE;
+	sel.target = target.raw();
+	sel.r = rect.into();
+	unsafe { v4l2_set_selection(dev.as_raw_fd(), &mut sel)? };
+	Ok(sel.r.into())
+}
+
+ioctl_readwrite_bad!(v4l2_get_selection, VIDIOC_G_SELECTION, v4l2_selection);
+ioctl_readwrite_bad!(v4l2_set_selection, VIDIOC_S_SELECTION, v4l2_selection);
@@ -0,0 +1,25 @@
+use std::{future::Future, io};
+
+use super::{format::Fraction, validate::IntegrityStats, CameraError, FrameInfo, VideoPixelFormat};
+
+// Anything frames can be read from: a `Camera`, a `CameraStream`, or a `ReplayCamera`
+// standing in for one when there's no hardware
+pub trait FrameSource {
+	fn format(&self) -> VideoPixelFormat;
+
+	// 0 for compressed formats, see `convert::Yuv444::convert`
+	fn bytes_per_line(&self) -> u32;
+
+	fn frame_interval(&self) -> io::Result<Fraction>;
+
+	// Waits for the next frame, starting the source first if needed
+	fn read_frame<F, R>(&mut self, func: F) -> impl Future<Output = Result<R, CameraError>>
+	where
+		F: FnOnce(&[u8], FrameInfo) -> R;
+
+	// Where `validate::decode_frame` counts corrupt frames, None if the source doesn't keep
+	// statistics
+	fn integrity(&mut self) -> Option<&mut IntegrityStats> {
+		None
+	}
+}
@@ -0,0 +1,238 @@
+use std::{
+	io,
+	time::{Duration, Instant},
+};
+
+use tokio::time::sleep;
+
+use super::{
+	controls::ControlValue,
+	device::DeviceSelector,
+	format::Fraction,
+	frame::{Frame, StreamStats},
+	source::FrameSource,
+	validate::IntegrityStats,
+	BufferMemory, Camera, CameraError, CameraSetting, FrameInfo, VideoPixelFormat,
+};
+
+// Everything that has to be set up again on a freshly opened camera
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraConfig {
+	pub format: VideoPixelFormat,
+	pub frame_interval: Option<Fraction>,
+	pub memory: BufferMemory,
+	// applied in order, later values for the same control replace earlier ones
+	pub controls: Vec<ControlValue>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectSettings {
+	// how often to look for the device while it's gone
+	pub poll: Duration,
+	// give up after this long, None waits forever
+	pub timeout: Option<Duration>,
+}
+
+impl Default for ReconnectSettings {
+	fn default() -> Self {
+		Self {
+			poll: Duration::from_millis(500),
+			timeout: Some(Duration::from_secs(30)),
+		}
+	}
+}
+
+// Streams from the camera with the given serial, reopening it whenever it disconnects.
+// The serial rather than the path since the kernel can give it a different /dev/videoN
+// when it comes back.
+pub struct SupervisedCamera {
+	serial: String,
+	config: CameraConfig,
+	settings: ReconnectSettings,
+	cam: Camera,
+	stats: StreamStats,
+	reconnects: u32,
+}
+
+impl SupervisedCamera {
+	pub async fn open(
+		serial: impl Into<String>,
+		config: CameraConfig,
+		settings: ReconnectSettings,
+	) -> io::Result<Self> {
+		let serial = serial.into();
+		let cam = open_configured(&serial, &config).await?;
+		Ok(Self {
+			serial,
+			config,
+			settings,
+			cam,
+			stats: StreamStats::default(),
+			reconnects: 0,
+		})
+	}
+
+	pub fn camera(&self) -> &Camera {
+		&self.cam
+	}
+
+	pub fn config(&self) -> &CameraConfig {
+		&self.config
+	}
+
+	pub fn stats(&self) -> StreamStats {
+		self.stats
+	}
+
+	pub fn reconnects(&self) -> u32 {
+		self.reconnects
+	}
+
+	// Remembered so it's set again after a reconnect
+	pub fn set<T: CameraSetting>(&mut self, value: i32) -> io::Result<i32> {
+		let value = self.cam.set::<T>(value)?;
+		self.remember(&[ControlValue::of::<T>(value)]);
+		Ok(value)
+	}
+
+	pub fn set_many(&mut self, values: &[ControlValue]) -> io::Result<Vec<i32>> {
+		let applied = self.cam.set_many(values)?;
+		let applied_values: Vec<ControlValue> = values
+			.iter()
+			.zip(&applied)
+			.map(|(v, &value)| ControlValue { id: v.id, value })
+			.collect();
+		self.remember(&applied_values);
+		Ok(applied)
+	}
+
+	// Waits out any disconnects. `func` is only called once, with the first frame that
+	// arrives.
+	pub async fn with_frame<F, R>(&mut self, func: F) -> Result<R, CameraError>
+	where
+		F: FnOnce(Frame) -> R,
+	{
+		self.next_frame(|data, info, dropped| {
+			func(Frame {
+				data,
+				info,
+				dropped,
+			})
+		})
+		.await
+	}
+
+	pub fn stop(&mut self) -> io::Result<()> {
+		self.cam.stop()
+	}
+
+	async fn reconnect(&mut self) -> Result<(), CameraError> {
+		let started = Instant::now();
+		loop {
+			sleep(self.settings.poll).await;
+			match open_configured(&self.serial, &self.config).await {
+				Ok(cam) => {
+					self.cam = cam;
+					self.reconnects += 1;
+					// the new stream's sequence numbers start from 0 again
+					self.stats.restart();
+					println!("Camera {} reconnected", self.serial);
+					return Ok(());
+				}
+				// the node can show up before the driver is ready for it
+				Err(e) => {
+					if let Some(timeout) = self.settings.timeout {
+						if started.elapsed() > timeout {
+							return Err(CameraError::Io(io::Error::new(
+								io::ErrorKind::TimedOut,
+								format!(
+									"Camera {} didn't come back within {timeout:?}: {e}",
+									self.serial
+								),
+							)));
+						}
+					}
+				}
+			}
+		}
+	}
+
+	async fn next_frame<F, R>(&mut self, func: F) -> Result<R, CameraError>
+	where
+		F: FnOnce(&[u8], FrameInfo, u32) -> R,
+	{
+		let mut func = Some(func);
+		loop {
+			if let Err(e) = self.cam.start() {
+				let err = self.cam.device_error(e);
+				if !err.is_disconnected() {
+					return Err(err);
+				}
+			}
+			let stats = &mut self.stats;
+			let res = self
+				.cam
+				.next_frame(|data, info| {
+					let dropped = stats.record(&info);
+					let func = func.take().expect("only called once");
+					func(data, info, dropped)
+				})
+				.await;
+			match res {
+				Err(e) if e.is_disconnected() => {
+					eprintln!("{e}, waiting for camera {} to come back", self.serial);
+					self.reconnect().await?;
+				}
+				res => return res,
+			}
+		}
+	}
+
+	fn remember(&mut self, values: &[ControlValue]) {
+		for value in values {
+			self.config.controls.retain(|c| c.id != value.id);
+			self.config.controls.push(*value);
+		}
+	}
+}
+
+// Reads through disconnects like `with_frame`
+impl FrameSource for SupervisedCamera {
+	fn format(&self) -> VideoPixelFormat {
+		self.cam.format()
+	}
+
+	fn bytes_per_line(&self) -> u32 {
+		self.cam.bytes_per_line()
+	}
+
+	fn frame_interval(&self) -> io::Result<Fraction> {
+		self.cam.frame_interval()
+	}
+
+	async fn read_frame<F, R>(&mut self, func: F) -> Result<R, CameraError>
+	where
+		F: FnOnce(&[u8], FrameInfo) -> R,
+	{
+		self.next_frame(|data, info, _| func(data, info)).await
+	}
+
+	fn integrity(&mut self) -> Option<&mut IntegrityStats> {
+		Some(&mut self.stats.integrity)
+	}
+}
+
+async fn open_configured(serial: &str, config: &CameraConfig) -> io::Result<Camera> {
+	let path = DeviceSelector::Serial(serial.to_string()).resolve()?;
+	let mut cam = match config.frame_interval {
+		Some(interval) => Camera::with_frame_interval(path, config.format, interval).await?,
+		None => Camera::with_format(path, config.format).await?,
+	};
+	if config.memory != cam.buffer_memory() {
+		cam.set_buffer_memory(config.memory)?;
+	}
+	if !config.controls.is_empty() {
+		cam.set_many(&config.controls)?;
+	}
+	Ok(cam)
+}
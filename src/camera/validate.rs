@@ -0,0 +1,230 @@
+use std::{fmt, io};
+
+use clap::ValueEnum;
+
+use super::{
+	convert::Yuv444, source::FrameSource, CameraError, FrameInfo, VideoPixelFormat, MJPEG_FMT,
+};
+
+const SOI: [u8; 2] = [0xFF, 0xD8];
+const EOI: [u8; 2] = [0xFF, 0xD9];
+
+// Errors out rather than skipping forever when the camera only sends garbage
+const MAX_SKIPPED: usize = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameDefect {
+	// the driver set V4L2_BUF_FLAG_ERROR
+	Flagged,
+	Empty,
+	MissingSoi,
+	// usually truncated, the rest of the frame was lost on the bus
+	MissingEoi,
+	Decode(String),
+}
+
+impl fmt::Display for FrameDefect {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Flagged => write!(f, "driver flagged the buffer as bad"),
+			Self::Empty => write!(f, "frame is empty"),
+			Self::MissingSoi => write!(f, "JPEG doesn't start with SOI"),
+			Self::MissingEoi => write!(f, "JPEG doesn't end with EOI"),
+			Self::Decode(err) => write!(f, "frame didn't decode: {err}"),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum CorruptFramePolicy {
+	// read the next frame instead
+	#[default]
+	Skip,
+	// fix the JPEG's markers and decode what's there, or repeat the previous frame if that
+	// doesn't work, so the frame count stays the same
+	Repair,
+	// return an error, like converting without checking
+	Fail,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IntegrityStats {
+	// every frame that failed a check, whatever happened to it after
+	pub corrupt: u64,
+	pub repaired: u64,
+	pub skipped: u64,
+	// replaced by the previous frame under `CorruptFramePolicy::Repair`
+	pub repeated: u64,
+}
+
+// Marker checks only, a frame can pass these and still fail to decode
+pub fn check_mjpeg(data: &[u8]) -> Result<(), FrameDefect> {
+	if data.is_empty() {
+		return Err(FrameDefect::Empty);
+	}
+	if !data.starts_with(&SOI) {
+		return Err(FrameDefect::MissingSoi);
+	}
+	if eoi_end(data).is_none() {
+		return Err(FrameDefect::MissingEoi);
+	}
+	Ok(())
+}
+
+// Where the JPEG ends. Some cameras pad bytesused past the EOI with zeros, so that's
+// allowed after it. 0xFF 0xD9 can't appear inside the entropy coded data, every 0xFF there
+// is followed by 0x00.
+fn eoi_end(data: &[u8]) -> Option<usize> {
+	let end = data.len() - data.iter().rev().take_while(|&&b| b == 0).count();
+	data[..end].ends_with(&EOI).then_some(end)
+}
+
+// Copies the JPEG into `out` without any padding, appending an EOI if it was truncated.
+// false if it doesn't even start like a JPEG.
+pub fn repair_mjpeg(data: &[u8], out: &mut Vec<u8>) -> bool {
+	if !data.starts_with(&SOI) {
+		return false;
+	}
+	out.clear();
+	match eoi_end(data) {
+		Some(end) => out.extend_from_slice(&data[..end]),
+		None => {
+			let end = data.len() - data.iter().rev().take_while(|&&b| b == 0).count();
+			out.extend_from_slice(&data[..end]);
+			out.extend_from_slice(&EOI);
+		}
+	}
+	true
+}
+
+enum Checked {
+	Valid,
+	Repaired,
+	Corrupt(FrameDefect),
+}
+
+// Converts the next frame from `source` into `planes`, checking it first and handling
+// corrupt ones by `policy`. Other formats than MJPEG get the error flag and length checks.
+// Counted in the source's `integrity` stats if it keeps them.
+pub async fn decode_frame(
+	source: &mut impl FrameSource,
+	planes: &mut Yuv444,
+	policy: CorruptFramePolicy,
+) -> Result<FrameInfo, CameraError> {
+	// a copy, reading frames needs the source borrowed
+	let mut stats = source.integrity().copied().unwrap_or_default();
+	let res = decode_counted(source, planes, policy, &mut stats).await;
+	if let Some(integrity) = source.integrity() {
+		*integrity = stats;
+	}
+	res
+}
+
+async fn decode_counted(
+	source: &mut impl FrameSource,
+	planes: &mut Yuv444,
+	policy: CorruptFramePolicy,
+	stats: &mut IntegrityStats,
+) -> Result<FrameInfo, CameraError> {
+	let format = source.format();
+	let stride = source.bytes_per_line();
+	let mut scratch = Vec::new();
+	let mut skipped = 0;
+	loop {
+		let (info, checked) = source
+			.read_frame(|data, info| {
+				let checked = check_and_convert(
+					planes,
+					format,
+					stride,
+					data,
+					&info,
+					policy == CorruptFramePolicy::Repair,
+					&mut scratch,
+				);
+				(info, checked)
+			})
+			.await?;
+		let defect = match checked? {
+			Checked::Valid => return Ok(info),
+			Checked::Repaired => {
+				stats.corrupt += 1;
+				stats.repaired += 1;
+				return Ok(info);
+			}
+			Checked::Corrupt(defect) => defect,
+		};
+		stats.corrupt += 1;
+		match policy {
+			CorruptFramePolicy::Fail => {
+				return Err(CameraError::Io(io::Error::new(
+					io::ErrorKind::InvalidData,
+					format!("Frame {}: {defect}", info.sequence),
+				)))
+			}
+			// `planes` still has the previous frame, decoding only writes it on success
+			CorruptFramePolicy::Repair => {
+				stats.repeated += 1;
+				return Ok(info);
+			}
+			CorruptFramePolicy::Skip => {
+				stats.skipped += 1;
+				skipped += 1;
+				if skipped == MAX_SKIPPED {
+					return Err(CameraError::Io(io::Error::new(
+						io::ErrorKind::InvalidData,
+						format!("{MAX_SKIPPED} corrupt frames in a row, last one: {defect}"),
+					)));
+				}
+			}
+		}
+	}
+}
+
+fn check_and_convert(
+	planes: &mut Yuv444,
+	format: VideoPixelFormat,
+	stride: u32,
+	data: &[u8],
+	info: &FrameInfo,
+	repair: bool,
+	scratch: &mut Vec<u8>,
+) -> io::Result<Checked> {
+	let mjpeg = format.format == MJPEG_FMT;
+	let defect = if info.is_error() {
+		Some(FrameDefect::Flagged)
+	} else if mjpeg {
+		check_mjpeg(data).err()
+	} else {
+		None
+	};
+	let defect = match defect {
+		Some(defect) => defect,
+		None => match convert(planes, format, stride, data)? {
+			Ok(()) => return Ok(Checked::Valid),
+			Err(defect) => defect,
+		},
+	};
+	if repair && mjpeg && repair_mjpeg(data, scratch) {
+		if let Ok(()) = convert(planes, format, stride, scratch)? {
+			return Ok(Checked::Repaired);
+		}
+	}
+	Ok(Checked::Corrupt(defect))
+}
+
+// Bad data is a defect, anything else (wrong size planes, unsupported format) a real error
+fn convert(
+	planes: &mut Yuv444,
+	format: VideoPixelFormat,
+	stride: u32,
+	data: &[u8],
+) -> io::Result<Result<(), FrameDefect>> {
+	match planes.convert(format, stride, data) {
+		Ok(()) => Ok(Ok(())),
+		Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+			Ok(Err(FrameDefect::Decode(e.to_string())))
+		}
+		Err(e) => Err(e),
+	}
+}
@@ -0,0 +1,295 @@
+#![allow(dead_code)]
+
+use std::ops::Mul;
+
+use nalgebra::{Matrix3, Matrix4, Point3, Quaternion, Rotation3, UnitQuaternion, Vector3};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rotation(UnitQuaternion<f64>);
+
+impl Rotation {
+	pub fn identity() -> Self {
+		Self(UnitQuaternion::identity())
+	}
+
+	// UR axis-angle: the direction is the axis, the norm is the angle in radians
+	pub fn from_rotation_vector([rx, ry, rz]: [f64; 3]) -> Self {
+		Self(UnitQuaternion::from_scaled_axis(Vector3::new(rx, ry, rz)))
+	}
+
+	pub fn to_rotation_vector(self) -> [f64; 3] {
+		self.0.scaled_axis().into()
+	}
+
+	pub fn from_quaternion([w, x, y, z]: [f64; 4]) -> Self {
+		Self(UnitQuaternion::from_quaternion(Quaternion::new(w, x, y, z)))
+	}
+
+	pub fn to_quaternion(self) -> [f64; 4] {
+		let q = self.0.quaternion();
+		[q.w, q.i, q.j, q.k]
+	}
+
+	// same convention as URScript rpy2rotvec: R = Rz(yaw) * Ry(pitch) * Rx(roll)
+	pub fn from_rpy([roll, pitch, yaw]: [f64; 3]) -> Self {
+		Self(UnitQuaternion::from_euler_angles(roll, pitch, yaw))
+	}
+
+	pub fn to_rpy(self) -> [f64; 3] {
+		let (roll, pitch, yaw) = self.0.euler_angles();
+		[roll, pitch, yaw]
+	}
+
+	pub fn from_matrix(m: &Matrix3<f64>) -> Self {
+		// goes through the closest orthonormal matrix, so slightly noisy inputs are fine
+		Self(UnitQuaternion::from_matrix(m))
+	}
+
+	pub fn matrix(&self) -> Matrix3<f64> {
+		self.0.to_rotation_matrix().into_inner()
+	}
+
+	pub fn from_unit_quaternion(q: UnitQuaternion<f64>) -> Self {
+		Self(q)
+	}
+
+	pub fn unit_quaternion(&self) -> UnitQuaternion<f64> {
+		self.0
+	}
+
+	pub fn compose(&self, other: &Rotation) -> Self {
+		Self(self.0 * other.0)
+	}
+
+	pub fn inverse(&self) -> Self {
+		Self(self.0.inverse())
+	}
+
+	pub fn apply(&self, v: &Vector3<f64>) -> Vector3<f64> {
+		self.0 * v
+	}
+
+	pub fn slerp(&self, other: &Rotation, t: f64) -> Self {
+		// UnitQuaternion::slerp already takes the shortest path
+		Self(self.0.slerp(&other.0, t))
+	}
+
+	pub fn angle(&self) -> f64 {
+		self.0.angle()
+	}
+
+	pub fn angle_to(&self, other: &Rotation) -> f64 {
+		self.0.angle_to(&other.0)
+	}
+}
+
+impl Default for Rotation {
+	fn default() -> Self {
+		Self::identity()
+	}
+}
+
+impl Mul for Rotation {
+	type Output = Rotation;
+
+	fn mul(self, rhs: Rotation) -> Self::Output {
+		self.compose(&rhs)
+	}
+}
+
+impl From<Rotation3<f64>> for Rotation {
+	fn from(value: Rotation3<f64>) -> Self {
+		Self(UnitQuaternion::from_rotation_matrix(&value))
+	}
+}
+
+// A rigid transform, laid out the same way UR poses are: translation in metres
+// followed by the rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Pose {
+	pub translation: Vector3<f64>,
+	pub rotation: Rotation,
+}
+
+impl Pose {
+	pub fn identity() -> Self {
+		Self::default()
+	}
+
+	pub fn new(translation: Vector3<f64>, rotation: Rotation) -> Self {
+		Self {
+			translation,
+			rotation,
+		}
+	}
+
+	// [x, y, z, rx, ry, rz], as used by URScript `p[...]` poses and the RTDE registers
+	pub fn from_ur([x, y, z, rx, ry, rz]: [f64; 6]) -> Self {
+		Self {
+			translation: Vector3::new(x, y, z),
+			rotation: Rotation::from_rotation_vector([rx, ry, rz]),
+		}
+	}
+
+	pub fn to_ur(self) -> [f64; 6] {
+		let [rx, ry, rz] = self.rotation.to_rotation_vector();
+		let t = &self.translation;
+		[t.x, t.y, t.z, rx, ry, rz]
+	}
+
+	// equivalent to URScript pose_trans(self, other)
+	pub fn compose(&self, other: &Pose) -> Self {
+		Self {
+			translation: self.translation + self.rotation.apply(&other.translation),
+			rotation: self.rotation.compose(&other.rotation),
+		}
+	}
+
+	pub fn inverse(&self) -> Self {
+		let rotation = self.rotation.inverse();
+		Self {
+			translation: -rotation.apply(&self.translation),
+			rotation,
+		}
+	}
+
+	pub fn transform_point(&self, p: &Point3<f64>) -> Point3<f64> {
+		Point3::from(self.rotation.apply(&p.coords) + self.translation)
+	}
+
+	pub fn transform_vector(&self, v: &Vector3<f64>) -> Vector3<f64> {
+		self.rotation.apply(v)
+	}
+
+	// linear in translation, SLERP in rotation
+	pub fn interpolate(&self, other: &Pose, t: f64) -> Self {
+		Self {
+			translation: self.translation.lerp(&other.translation, t),
+			rotation: self.rotation.slerp(&other.rotation, t),
+		}
+	}
+
+	pub fn translation_distance(&self, other: &Pose) -> f64 {
+		(self.translation - other.translation).norm()
+	}
+
+	pub fn rotation_distance(&self, other: &Pose) -> f64 {
+		self.rotation.angle_to(&other.rotation)
+	}
+
+	// Single scalar for ordering/thresholding poses, `radius` converts radians to metres
+	// (i.e. the arc length swept at that distance from the rotation centre).
+	pub fn distance(&self, other: &Pose, radius: f64) -> f64 {
+		self.translation_distance(other) + radius * self.rotation_distance(other)
+	}
+
+	pub fn matrix(&self) -> Matrix4<f64> {
+		Transform::from(*self).0
+	}
+}
+
+impl Mul for Pose {
+	type Output = Pose;
+
+	fn mul(self, rhs: Pose) -> Self::Output {
+		self.compose(&rhs)
+	}
+}
+
+impl From<[f64; 6]> for Pose {
+	fn from(value: [f64; 6]) -> Self {
+		Self::from_ur(value)
+	}
+}
+
+impl From<Pose> for [f64; 6] {
+	fn from(value: Pose) -> Self {
+		value.to_ur()
+	}
+}
+
+// Homogeneous 4x4 form, for interop with anything that wants plain matrices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform(pub Matrix4<f64>);
+
+impl Transform {
+	pub fn identity() -> Self {
+		Self(Matrix4::identity())
+	}
+
+	pub fn from_parts(rotation: &Matrix3<f64>, translation: &Vector3<f64>) -> Self {
+		let mut m = Matrix4::identity();
+		m.fixed_view_mut::<3, 3>(0, 0).copy_from(rotation);
+		m.fixed_view_mut::<3, 1>(0, 3).copy_from(translation);
+		Self(m)
+	}
+
+	pub fn from_rows(rows: [[f64; 4]; 4]) -> Self {
+		Self(Matrix4::from_fn(|i, j| rows[i][j]))
+	}
+
+	pub fn to_rows(self) -> [[f64; 4]; 4] {
+		let mut rows = [[0.; 4]; 4];
+		for (i, row) in rows.iter_mut().enumerate() {
+			for (j, v) in row.iter_mut().enumerate() {
+				*v = self.0[(i, j)];
+			}
+		}
+		rows
+	}
+
+	pub fn rotation(&self) -> Matrix3<f64> {
+		self.0.fixed_view::<3, 3>(0, 0).into_owned()
+	}
+
+	pub fn translation(&self) -> Vector3<f64> {
+		self.0.fixed_view::<3, 1>(0, 3).into_owned()
+	}
+
+	pub fn compose(&self, other: &Transform) -> Self {
+		Self(self.0 * other.0)
+	}
+
+	// rigid inverse, cheaper and better conditioned than a general 4x4 inverse
+	pub fn inverse(&self) -> Self {
+		let r_t = self.rotation().transpose();
+		Self::from_parts(&r_t, &(-r_t * self.translation()))
+	}
+
+	pub fn transform_point(&self, p: &Point3<f64>) -> Point3<f64> {
+		Point3::from(self.rotation() * p.coords + self.translation())
+	}
+
+	pub fn to_pose(self) -> Pose {
+		Pose {
+			translation: self.translation(),
+			rotation: Rotation::from_matrix(&self.rotation()),
+		}
+	}
+}
+
+impl Default for Transform {
+	fn default() -> Self {
+		Self::identity()
+	}
+}
+
+impl Mul for Transform {
+	type Output = Transform;
+
+	fn mul(self, rhs: Transform) -> Self::Output {
+		self.compose(&rhs)
+	}
+}
+
+impl From<Pose> for Transform {
+	fn from(pose: Pose) -> Self {
+		Self::from_parts(&pose.rotation.matrix(), &pose.translation)
+	}
+}
+
+impl From<Transform> for Pose {
+	fn from(value: Transform) -> Self {
+		value.to_pose()
+	}
+}
@@ -1,110 +1,695 @@
-use std::net::Ipv4Addr;
+use std::{
+	net::Ipv4Addr,
+	path::{Path, PathBuf},
+	time::Duration,
+};
 
-use camera::{Brightness, ExposureAuto, Gain, Gamma};
-use color_eyre::eyre::Result;
-use video::{Encoder, VideoContext};
-use zune_jpeg::{
-	zune_core::{colorspace::ColorSpace, options::DecoderOptions},
-	JpegDecoder,
+use calibration::{
+	checkerboard::Checkerboard,
+	hand_eye::{self, CaptureSettings, HandEyeResult},
+	intrinsics::{calibrate_dir, CheckerboardDetector, IntrinsicCalibration},
+};
+use camera::{
+	convert::Yuv444,
+	device::{enumerate_devices, DeviceSelector},
+	events::{CameraEvent, EventKind},
+	exposure::{lock_exposure, ExposureSettings},
+	focus::FocusSweepSettings,
+	format::{fourcc_name, FrameIntervals, FrameSizes},
+	group::CameraGroup,
+	profile::{CameraProfile, ProfileStore},
+	replay::ReplayCamera,
+	selection::{Crop, Rect},
+	source::FrameSource,
+	supervisor::{CameraConfig, ReconnectSettings, SupervisedCamera},
+	validate::{decode_frame, CorruptFramePolicy},
+	Brightness, ExposureAuto, Gain, Gamma, MJPEG_FMT,
 };
+use color_eyre::eyre::{ensure, eyre, Result};
+use geometry::Pose;
+use scan::{ExportFormat, Pattern, ScanPlan, ScanSettings, Session};
+use video::{Encoder, VideoContext};
 
 use clap::{Parser, Subcommand};
 
+mod calibration;
 mod camera;
 mod compute;
+mod geometry;
 mod robot;
+mod scan;
 mod video;
 
+const ROBOT_ADDR: &str = "169.254.129.110:0";
+const CALLBACK_ADDR: Ipv4Addr = Ipv4Addr::new(169, 254, 129, 50);
+
 #[derive(Debug, Parser)]
 struct Cli {
 	#[command(subcommand)]
 	command: Option<Command>,
+	// camera settings profiles, TOML or .json
+	#[arg(long, global = true, default_value = "camera-profiles.toml")]
+	profiles: PathBuf,
+	// applied to each camera as it opens, if one is saved for it
+	#[arg(long, global = true, default_value = "default")]
+	profile: String,
 }
 
 #[derive(Debug, Clone, Subcommand)]
 enum Command {
-	Stream,
+	Stream {
+		// a directory of JPEGs or a recording to use instead of /dev/video0
+		#[arg(long)]
+		replay: Option<PathBuf>,
+		// left,top,width,height of the part of the frame to keep
+		#[arg(long, value_delimiter = ',', num_args = 4)]
+		crop: Option<Vec<u32>>,
+		// what to do with truncated or corrupt frames
+		#[arg(long, value_enum, default_value_t = CorruptFramePolicy::Skip)]
+		corrupt: CorruptFramePolicy,
+	},
 	Arm,
+	Devices,
+	Formats {
+		// device path, serial:<serial> or bus:<bus info>
+		#[arg(default_value = "/dev/video0")]
+		device: DeviceSelector,
+	},
+	Controls {
+		#[arg(default_value = "/dev/video0")]
+		device: DeviceSelector,
+	},
+	// prints control, source change and end of stream events until interrupted
+	Events {
+		#[arg(default_value = "/dev/video0")]
+		device: DeviceSelector,
+	},
+	// sweeps focus and leaves it at the sharpest position
+	Focus {
+		#[arg(default_value = "/dev/video0")]
+		device: DeviceSelector,
+		#[arg(long, default_value_t = 16)]
+		steps: usize,
+	},
+	Camera {
+		#[command(subcommand)]
+		command: CameraCommand,
+	},
+	// streams several cameras together and reports how closely their frames line up
+	Sync {
+		#[arg(required = true)]
+		devices: Vec<DeviceSelector>,
+		#[arg(long, default_value_t = 100)]
+		count: usize,
+		// milliseconds
+		#[arg(long, default_value_t = 10.)]
+		tolerance: f64,
+	},
+	Calibrate {
+		images: PathBuf,
+		#[arg(long, default_value_t = 9, value_parser = inner_corners)]
+		cols: usize,
+		#[arg(long, default_value_t = 6, value_parser = inner_corners)]
+		rows: usize,
+		#[arg(long, default_value_t = 0.025)]
+		square: f64,
+		#[arg(short, long, default_value = "intrinsics.json")]
+		output: PathBuf,
+	},
+	// Poses are sampled around the centre, which should have the checkerboard in view
+	HandEye {
+		// x,y,z,rx,ry,rz of the flange in the base frame
+		#[arg(long, value_delimiter = ',', num_args = 6, allow_hyphen_values = true)]
+		centre: Vec<f64>,
+		#[arg(long, default_value = "intrinsics.json")]
+		intrinsics: PathBuf,
+		#[arg(long, default_value_t = 9, value_parser = inner_corners)]
+		cols: usize,
+		#[arg(long, default_value_t = 6, value_parser = inner_corners)]
+		rows: usize,
+		#[arg(long, default_value_t = 0.025)]
+		square: f64,
+		// radians to tilt the tool about x and y, and roll it about z
+		#[arg(long, default_value_t = 0.25)]
+		tilt: f64,
+		#[arg(long, default_value_t = 0.3)]
+		roll: f64,
+		#[arg(long, default_value = "/dev/video0")]
+		camera: DeviceSelector,
+		#[arg(short, long, default_value = "hand-eye.json")]
+		output: PathBuf,
+	},
+	Scan {
+		// reopened and resumed if it already holds a session
+		session: PathBuf,
+		#[arg(long, value_delimiter = ',', num_args = 3)]
+		centre: Option<Vec<f64>>,
+		#[arg(long)]
+		radius: Option<f64>,
+		#[arg(long, value_enum, default_value_t = Pattern::Hemisphere)]
+		pattern: Pattern,
+		#[arg(long, default_value_t = 24)]
+		count: usize,
+		// hand-eye result, the camera is assumed to sit on the flange without one
+		#[arg(long)]
+		hand_eye: Option<PathBuf>,
+		#[arg(long, default_value = "/dev/video0")]
+		camera: DeviceSelector,
+		// find an exposure for the first viewpoint and keep it for the whole scan
+		#[arg(long)]
+		auto_exposure: bool,
+	},
+	Export {
+		session: PathBuf,
+		#[arg(long, default_value = "intrinsics.json")]
+		intrinsics: PathBuf,
+		#[arg(short, long, default_value = "export")]
+		output: PathBuf,
+		#[arg(long, value_enum, default_value_t = ExportFormat::All)]
+		format: ExportFormat,
+	},
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum CameraCommand {
+	// save, apply or compare control snapshots, keyed by the camera's serial
+	Profile {
+		#[command(subcommand)]
+		action: ProfileAction,
+	},
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum ProfileAction {
+	// stores the camera's current controls under --profile
+	Save {
+		#[arg(default_value = "/dev/video0")]
+		device: DeviceSelector,
+	},
+	Apply {
+		#[arg(default_value = "/dev/video0")]
+		device: DeviceSelector,
+	},
+	// controls that differ between the saved profile and the camera
+	Diff {
+		#[arg(default_value = "/dev/video0")]
+		device: DeviceSelector,
+	},
 }
 
 impl Cli {
 	pub async fn run(self) -> Result<()> {
 		use Command as C;
-		let command = self.command.unwrap_or(C::Stream);
+		let command = self.command.unwrap_or(C::Stream {
+			replay: None,
+			crop: None,
+			corrupt: CorruptFramePolicy::default(),
+		});
+		let profiles = ProfileStore::load(&self.profiles)?;
 		match command {
-			C::Stream => {
+			C::Stream {
+				replay: Some(path),
+				crop,
+				corrupt,
+			} => {
+				let _ctx = VideoContext::new();
+				let mut source = ReplayCamera::open(&path, None)?;
+				record(
+					&mut source,
+					Path::new("output.mkv"),
+					10,
+					crop.map(|c| Rect::new(c[0] as i32, c[1] as i32, c[2], c[3])),
+					corrupt,
+				)
+				.await?;
+			}
+			C::Stream {
+				replay: None,
+				crop,
+				corrupt,
+			} => {
 				let _ctx = VideoContext::new();
 				// test();
 
-				let (mut cam, mut buffers) = camera::Camera::new("/dev/video0").await?;
-				cam.set::<ExposureAuto>(3);
-				// cam.set::<Exposure>(166);
-				cam.set::<Brightness>(128);
-				cam.set::<Gamma>(133);
-				cam.set::<Gain>(0);
+				let mut cam =
+					camera::Camera::with_profile("/dev/video0", &profiles, &self.profile).await?;
+				if profiles.get(cam.info(), &self.profile).is_none() {
+					println!(
+						"No {} profile saved for this camera, using the built-in settings",
+						self.profile
+					);
+					cam.set::<ExposureAuto>(3)?;
+					// cam.set::<Exposure>(166)?;
+					cam.set::<Brightness>(128)?;
+					cam.set::<Gamma>(133)?;
+					cam.set::<Gain>(0)?;
+				}
+				let crop = match crop {
+					Some(c) => match cam.crop(Rect::new(c[0] as i32, c[1] as i32, c[2], c[3]))? {
+						Crop::Sensor(rect) => {
+							println!("Camera is cropping to {rect:?}");
+							None
+						}
+						Crop::Software(rect) => Some(rect),
+					},
+					None => None,
+				};
 
-				for _ in 0..buffers.len() {
-					cam.capture_frame(&mut buffers).await?;
+				for _ in 0..cam.num_buffers() {
+					cam.capture_frame().await?;
 				}
 
-				// let frame_data = cam.capture_frame(&mut buffers).await?;
+				// let frame_data = cam.capture_frame().await?;
 
 				// let mut image = File::create("woah.jpeg")?;
 				// image.write(frame_data)?;
 
-				let mut raw = vec![0; 1920 * 1080 * 3];
-				let mut out = vec![0; 1920 * 1080 * 3];
-				let mut encoder = Encoder::new();
-				let mut stream = cam.stream(&mut buffers)?;
-				for i in 0..10 {
-					println!("{i}");
-					stream
-						.with_frame(|frame| -> Result<()> {
-							let mut decoder = JpegDecoder::new_with_options(
-								frame,
-								DecoderOptions::new_cmd()
-									.jpeg_set_out_colorspace(ColorSpace::YCbCr),
-							);
-							decoder.decode_into(&mut raw)?;
-							Ok(())
-						})
-						.await??;
-					let min = raw.iter().min().unwrap();
-					let max = raw.iter().max().unwrap();
-					println!("min {min}\nmax{max}");
-					println!("[{}, {}, {}]", raw[0], raw[1], raw[2]);
-					for i in 0..1080 {
-						for j in 0..1920 {
-							for k in 0..3 {
-								out[k * 1920 * 1080 + i * 1920 + j] = raw[i * 1920 * 3 + j * 3 + k];
+				let mut stream = cam.stream()?;
+				record(&mut stream, Path::new("output.mkv"), 10, crop, corrupt).await?;
+				let stats = stream.stats();
+				println!(
+					"finish, {} frames, {} dropped, {} with errors, {:.1} fps",
+					stats.frames,
+					stats.dropped,
+					stats.errors,
+					stats.fps().unwrap_or(0.)
+				);
+				let integrity = stats.integrity;
+				println!(
+					"{} corrupt: {} repaired, {} skipped, {} repeated",
+					integrity.corrupt, integrity.repaired, integrity.skipped, integrity.repeated
+				);
+				stream.stop()?;
+			}
+			C::Arm => {
+				let mut r = robot::Robot::start_with_addr(ROBOT_ADDR, Some(CALLBACK_ADDR)).await?;
+				println!("Connected!");
+				r.servo_j([-1.5, -1.5, -1.5, 0., 1.5, 0.], 0.8, 0.1, 0.1, 0.1, 300.0)
+					.await?;
+			}
+			C::Devices => {
+				for device in enumerate_devices()? {
+					println!(
+						"{}: {} ({}) at {}, serial {}",
+						device.path.display(),
+						device.card,
+						device.driver,
+						device.bus_info,
+						device.serial.as_deref().unwrap_or("unknown"),
+					);
+				}
+			}
+			C::Formats { device } => {
+				for format in camera::Camera::probe_formats(device.resolve()?)? {
+					println!(
+						"{} ({}){}",
+						fourcc_name(format.fourcc),
+						format.description,
+						if Yuv444::is_supported(format.fourcc) {
+							""
+						} else {
+							", no converter"
+						}
+					);
+					match format.sizes {
+						FrameSizes::Discrete(sizes) => {
+							for size in sizes {
+								let rates = match size.intervals {
+									FrameIntervals::Discrete(intervals) => intervals
+										.iter()
+										.map(|i| format!("{:.1}", i.fps()))
+										.collect::<Vec<_>>()
+										.join(", "),
+									FrameIntervals::Stepwise { min, max, .. } => {
+										format!("{:.1} - {:.1}", max.fps(), min.fps())
+									}
+								};
+								println!("  {}x{} @ {rates} fps", size.width, size.height);
 							}
 						}
+						FrameSizes::Stepwise {
+							min_width,
+							max_width,
+							min_height,
+							max_height,
+							..
+						} => println!("  {min_width}x{min_height} - {max_width}x{max_height}"),
 					}
-					encoder.encode(
-						i * 400,
-						&out[..1920 * 1080],
-						&out[1920 * 1080..1920 * 1080 * 2],
-						&out[1920 * 1080 * 2..1920 * 1080 * 3],
+				}
+			}
+			C::Controls { device } => {
+				for control in camera::Camera::probe_controls(device.resolve()?)? {
+					println!(
+						"{:#010x} {} ({:?}): {}..={} step {} default {}{}{}",
+						control.id,
+						control.name,
+						control.kind,
+						control.minimum,
+						control.maximum,
+						control.step,
+						control.default,
+						if control.is_read_only() {
+							" read-only"
+						} else {
+							""
+						},
+						if control.is_inactive() {
+							" inactive"
+						} else {
+							""
+						},
 					);
+					for entry in &control.menu {
+						println!("  {}: {}", entry.index, entry.name);
+					}
 				}
-				println!("finish");
-				encoder.finish();
-				stream.stop().await?;
 			}
-			C::Arm => {
-				let addr = "169.254.129.110:0";
-				let callback_addr = Some(Ipv4Addr::new(169, 254, 129, 50));
-
-				let mut r = robot::Robot::start_with_addr(addr, callback_addr).await?;
-				println!("Connected!");
-				r.servo_j([-1.5, -1.5, -1.5, 0., 1.5, 0.], 0.8, 0.1, 0.1, 0.1, 300.0)
+			C::Events { device } => {
+				let cam = camera::Camera::new(device.resolve()?).await?;
+				let mut events = cam.events(&[
+					EventKind::Control,
+					EventKind::SourceChange,
+					EventKind::EndOfStream,
+				])?;
+				println!("Subscribed to {:?}", events.kinds());
+				loop {
+					let event = events.next().await?;
+					match event.event {
+						CameraEvent::Control {
+							id, value, changes, ..
+						} => println!("{:#010x} changed to {value} ({changes:#x})", id),
+						other => println!("{other:?}"),
+					}
+				}
+			}
+			C::Focus { device, steps } => {
+				let mut cam =
+					camera::Camera::with_profile(device.resolve()?, &profiles, &self.profile)
+						.await?;
+				let sweep = cam
+					.focus_sweep(FocusSweepSettings {
+						steps,
+						..Default::default()
+					})
 					.await?;
+				for (focus, sharpness) in &sweep.samples {
+					println!("{focus}: {sharpness:.1}");
+				}
+				println!("Focus set to {} ({:.1})", sweep.best, sweep.sharpness);
+			}
+			C::Camera {
+				command: CameraCommand::Profile { action },
+			} => match action {
+				ProfileAction::Save { device } => {
+					let cam = camera::Camera::new(device.resolve()?).await?;
+					let profile = CameraProfile::capture(&cam)?;
+					println!(
+						"Saved {} controls as {} for {device}",
+						profile.controls.len(),
+						self.profile
+					);
+					let mut profiles = profiles;
+					profiles.insert(cam.info(), &self.profile, profile);
+					profiles.save(&self.profiles)?;
+				}
+				ProfileAction::Apply { device } => {
+					let mut cam = camera::Camera::new(device.resolve()?).await?;
+					let profile = profiles
+						.get(cam.info(), &self.profile)
+						.ok_or_else(|| eyre!("No {} profile saved for {device}", self.profile))?;
+					let applied = profile.apply(&mut cam)?;
+					println!("Applied {} controls to {device}", applied.len());
+				}
+				ProfileAction::Diff { device } => {
+					let cam = camera::Camera::new(device.resolve()?).await?;
+					let profile = profiles
+						.get(cam.info(), &self.profile)
+						.ok_or_else(|| eyre!("No {} profile saved for {device}", self.profile))?;
+					let diffs = profile.diff(&cam)?;
+					if diffs.is_empty() {
+						println!("{device} matches {}", self.profile);
+					}
+					let show =
+						|value: Option<i32>| value.map_or("-".to_string(), |v| v.to_string());
+					for diff in diffs {
+						println!(
+							"{:#010x} {}: saved {}, current {}",
+							diff.id,
+							diff.name,
+							show(diff.saved),
+							show(diff.current)
+						);
+					}
+				}
+			},
+			C::Sync {
+				devices,
+				count,
+				tolerance,
+			} => {
+				ensure!(
+					tolerance.is_finite() && tolerance >= 0.,
+					"--tolerance has to be at least 0ms, not {tolerance}"
+				);
+				let mut cameras = Vec::with_capacity(devices.len());
+				for device in &devices {
+					cameras.push(
+						camera::Camera::with_profile(device.resolve()?, &profiles, &self.profile)
+							.await?,
+					);
+				}
+				let mut group =
+					CameraGroup::new(cameras, Duration::from_secs_f64(tolerance / 1000.));
+				for _ in 0..count {
+					let set = group.next().await?;
+					println!("skew {:.2}ms", set.skew.as_secs_f64() * 1000.);
+				}
+				for ((device, skew), stats) in devices.iter().zip(group.skew()).zip(group.stats()) {
+					println!(
+						"{device}: {} matched, {} unmatched, {} dropped, offset mean {:.2}ms max {:.2}ms",
+						skew.matched,
+						skew.unmatched,
+						stats.dropped,
+						skew.mean_offset() * 1000.,
+						skew.max_offset * 1000.,
+					);
+				}
+				group.stop().await?;
+			}
+			C::Calibrate {
+				images,
+				cols,
+				rows,
+				square,
+				output,
+			} => {
+				let board = Checkerboard { cols, rows, square };
+				let calibration = calibrate_dir(&board, &images)?;
+				for image in &calibration.images {
+					println!("{}: {:.3}px", image.name, image.rms);
+				}
+				for name in &calibration.rejected {
+					println!("{name}: board not found");
+				}
+				println!("RMS reprojection error {:.3}px", calibration.rms);
+				println!("{:#?}", calibration.intrinsics);
+				calibration.save(&output)?;
+			}
+			C::HandEye {
+				centre,
+				intrinsics,
+				cols,
+				rows,
+				square,
+				tilt,
+				roll,
+				camera,
+				output,
+			} => {
+				let mut detector = CheckerboardDetector {
+					board: Checkerboard { cols, rows, square },
+					intrinsics: IntrinsicCalibration::load(&intrinsics)?.intrinsics,
+				};
+				let centre = Pose::from_ur([
+					centre[0], centre[1], centre[2], centre[3], centre[4], centre[5],
+				]);
+				let mut robot =
+					robot::Robot::start_with_addr(ROBOT_ADDR, Some(CALLBACK_ADDR)).await?;
+				let mut cam =
+					camera::Camera::with_profile(camera.resolve()?, &profiles, &self.profile)
+						.await?;
+				// the detector decodes frames as JPEGs
+				ensure!(
+					cam.format().format == MJPEG_FMT,
+					"Hand-eye calibration needs an MJPEG camera, this one streams {}",
+					fourcc_name(cam.format().format)
+				);
+				let mut stream = cam.stream()?;
+				let result = hand_eye::calibrate(
+					&mut robot,
+					&mut stream,
+					&mut detector,
+					centre,
+					tilt,
+					roll,
+					CaptureSettings::default(),
+				)
+				.await?;
+				stream.stop()?;
+				println!("Saving {:?} to {}", result.method, output.display());
+				result.save(&output)?;
+			}
+			C::Scan {
+				session,
+				centre,
+				radius,
+				pattern,
+				count,
+				hand_eye,
+				camera,
+				auto_exposure,
+			} => {
+				let mut session = if Session::exists(&session) {
+					println!("Resuming scan in {}", session.display());
+					Session::open(&session)?
+				} else {
+					let centre =
+						centre.ok_or_else(|| eyre!("--centre is needed for a new scan"))?;
+					let radius =
+						radius.ok_or_else(|| eyre!("--radius is needed for a new scan"))?;
+					let camera_to_flange = match hand_eye {
+						Some(path) => HandEyeResult::load(path)?.camera_to_flange(),
+						None => Pose::identity(),
+					};
+					let plan =
+						ScanPlan::new([centre[0], centre[1], centre[2]], radius, pattern, count);
+					Session::create(&session, plan, camera_to_flange)?
+				};
+
+				let mut robot =
+					robot::Robot::start_with_addr(ROBOT_ADDR, Some(CALLBACK_ADDR)).await?;
+				let mut cam = camera::Camera::new(camera.resolve()?).await?;
+				// what's applied is set again whenever the camera is reopened
+				let mut controls = match profiles.get(cam.info(), &self.profile) {
+					Some(profile) => profile.apply(&mut cam)?,
+					None => Vec::new(),
+				};
+				if auto_exposure {
+					let lock = lock_exposure(&mut cam, ExposureSettings::default()).await?;
+					println!(
+						"Exposure locked at {}, gain {}, mean luma {:.1} after {} adjustments{}",
+						lock.exposure,
+						lock.gain,
+						lock.stats.mean,
+						lock.iterations,
+						if lock.converged {
+							""
+						} else {
+							", short of the target"
+						}
+					);
+					controls.extend(lock.values());
+				}
+				match cam.info().serial.clone() {
+					Some(serial) => {
+						let config = CameraConfig {
+							format: cam.format(),
+							frame_interval: cam.frame_interval().ok(),
+							memory: cam.buffer_memory(),
+							controls,
+						};
+						// the supervisor opens the device itself
+						drop(cam);
+						let mut source =
+							SupervisedCamera::open(serial, config, ReconnectSettings::default())
+								.await?;
+						scan::run(
+							&mut session,
+							&mut robot,
+							&mut source,
+							ScanSettings::default(),
+						)
+						.await?;
+						source.stop()?;
+						if source.reconnects() > 0 {
+							println!("Camera reconnected {} times", source.reconnects());
+						}
+					}
+					None => {
+						eprintln!(
+							"{} has no serial to reopen it by, a disconnect ends the scan",
+							cam.info().path.display()
+						);
+						let mut stream = cam.stream()?;
+						scan::run(
+							&mut session,
+							&mut robot,
+							&mut stream,
+							ScanSettings::default(),
+						)
+						.await?;
+						stream.stop()?;
+					}
+				}
+				println!("Scan complete");
+			}
+			C::Export {
+				session,
+				intrinsics,
+				output,
+				format,
+			} => {
+				let session = Session::open(&session)?;
+				let intrinsics = IntrinsicCalibration::load(&intrinsics)?.intrinsics;
+				scan::export(&session, &intrinsics, &output, format)?;
+				println!(
+					"Exported {} images to {}",
+					session.manifest().captures.len(),
+					output.display()
+				);
 			}
 		}
 		Ok(())
 	}
 }
 
+// Converts `count` frames, crops them to `crop` if given, and encodes them into `output`
+async fn record(
+	source: &mut impl FrameSource,
+	output: &Path,
+	count: i64,
+	crop: Option<Rect>,
+	policy: CorruptFramePolicy,
+) -> Result<()> {
+	let format = source.format();
+	let mut frame = Yuv444::new(format.width, format.height);
+	let mut cropped = crop.map(|rect| Yuv444::new(rect.width, rect.height));
+	let (width, height) = crop.map_or((format.width, format.height), |r| (r.width, r.height));
+	let mut encoder = Encoder::new(output, width, height, source.frame_interval()?);
+	for i in 0..count {
+		decode_frame(source, &mut frame, policy).await?;
+		let planes = match (crop, &mut cropped) {
+			(Some(rect), Some(out)) => {
+				frame.crop(rect, out)?;
+				&*out
+			}
+			_ => &frame,
+		};
+		encoder.encode(i, &planes.y, &planes.cb, &planes.cr);
+	}
+	encoder.finish();
+	Ok(())
+}
+
+// Checkerboard side, which needs at least 2 inner corners to tell the board's orientation
+fn inner_corners(arg: &str) -> std::result::Result<usize, String> {
+	match arg.parse() {
+		Ok(n) if n >= 2 => Ok(n),
+		Ok(_) => Err("the board needs at least 2 inner corners along each side".into()),
+		Err(e) => Err(format!("{e}")),
+	}
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
 	color_eyre::install()?;
@@ -112,3 +697,47 @@ async fn main() -> Result<()> {
 	let cli = Cli::parse();
 	cli.run().await
 }
+
+#[cfg(test)]
+mod tests {
+	use std::{env, fs, io};
+
+	use camera::{format::Fraction, CameraError};
+
+	use super::*;
+
+	// Records the replay fixture and reads the recording back, which is lossless
+	#[tokio::test]
+	async fn records_a_replay() {
+		let _ctx = VideoContext::new();
+		let frames = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/replay");
+		let output = env::temp_dir().join(format!("remat-record-{}.mkv", std::process::id()));
+		let interval = Fraction::new(1, 100);
+
+		let mut source = ReplayCamera::from_jpegs(&frames, interval).unwrap();
+		record(&mut source, &output, 3, None, CorruptFramePolicy::Fail)
+			.await
+			.unwrap();
+
+		let mut expected = ReplayCamera::from_jpegs(&frames, interval).unwrap();
+		let mut recorded = ReplayCamera::from_video(&output, None).unwrap();
+		let format = recorded.format();
+		assert_eq!((format.width, format.height), (16, 16));
+		let mut want = Yuv444::new(16, 16);
+		let mut got = Yuv444::new(16, 16);
+		for _ in 0..3 {
+			decode_frame(&mut expected, &mut want, CorruptFramePolicy::Fail)
+				.await
+				.unwrap();
+			decode_frame(&mut recorded, &mut got, CorruptFramePolicy::Fail)
+				.await
+				.unwrap();
+			assert_eq!((&got.y, &got.cb, &got.cr), (&want.y, &want.cb, &want.cr));
+		}
+		match decode_frame(&mut recorded, &mut got, CorruptFramePolicy::Fail).await {
+			Err(CameraError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+			res => panic!("expected 3 frames in the recording, got {res:?}"),
+		}
+		fs::remove_file(&output).unwrap();
+	}
+}
@@ -1,23 +1,34 @@
 #![allow(dead_code)]
-use std::net::{IpAddr, Ipv4Addr};
+use std::{
+	net::{IpAddr, Ipv4Addr},
+	time::Duration,
+};
 
 use callback::{CallbackClient, CallbackServer};
 use color_eyre::eyre::{bail, Context, OptionExt, Result};
 use rtde::RtdeClient;
-use tokio::net::{lookup_host, ToSocketAddrs};
+use tokio::{
+	net::{lookup_host, ToSocketAddrs},
+	time::sleep,
+};
 
 use script::ScriptClient;
+use state::{PoseTracker, STATE_FREQUENCY};
+
+use crate::geometry::Pose;
 
 mod callback;
 mod commands;
 mod recipes;
 mod rtde;
 mod script;
+pub mod state;
 
 pub struct Robot {
 	rtde: RtdeClient,
 	script: ScriptClient,
 	callback: CallbackClient,
+	state: PoseTracker,
 }
 
 impl Robot {
@@ -56,11 +67,16 @@ impl Robot {
 		println!("Script set up");
 		let callback = callback.accept().await?;
 		println!("Callback accepted");
+		let mut state_rtde = RtdeClient::new(addr).await?;
+		state_rtde.setup_outputs(STATE_FREQUENCY).await?;
+		let state = PoseTracker::spawn(state_rtde);
+		println!("State stream started");
 
 		Ok(Self {
 			rtde,
 			script,
 			callback,
+			state,
 		})
 	}
 
@@ -111,4 +127,40 @@ impl Robot {
 		self.callback.non_awaitable().await;
 		Ok(())
 	}
+
+	// Blocks until the controller reports the move as finished. The pose is the TCP pose
+	// in the base frame, so with a zero TCP offset this is the flange pose.
+	pub async fn move_l(&mut self, pose: Pose, speed: f64, acceleration: f64) -> Result<()> {
+		self.rtde
+			.send(recipes::Recipe::JCommand {
+				command: 3,
+				// movel shares the JCommand registers, with the pose in place of the joints
+				q: pose.to_ur(),
+				speed,
+				acceleration,
+				time: 0.,
+				lookahead_time: 0.,
+				gain: 0.,
+			})
+			.await?;
+		self.callback.awaitable().await;
+		Ok(())
+	}
+
+	pub fn state(&self) -> &PoseTracker {
+		&self.state
+	}
+
+	// TCP pose at host CLOCK_MONOTONIC `time` (a frame timestamp, say). Waits a few state
+	// periods in case the controller hasn't reported that far yet.
+	pub async fn pose_at(&self, time: Duration) -> Result<Pose> {
+		let period = Duration::from_secs_f64(1. / STATE_FREQUENCY);
+		for _ in 0..10 {
+			if let Some(pose) = self.state.pose_at(time) {
+				return Ok(pose);
+			}
+			sleep(period).await;
+		}
+		bail!("No robot state recorded around {time:?}")
+	}
 }
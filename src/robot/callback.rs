@@ -36,11 +36,13 @@ pub struct CallbackClient {
 
 async fn callback_event_loop(mut conn: TcpStream, mut rx: Receiver<Option<OneSender<()>>>) {
 	while let Some(req) = rx.recv().await {
-		conn.write(&[0])
+		// the script reads the op id with socket_read_binary_integer, which expects a full
+		// big endian i32, and answers awaitable ops with socket_send_int
+		conn.write_i32(0)
 			.await
 			.expect("Callback conn unexpectedly closed");
 		if let Some(req) = req {
-			conn.read_u8().await.expect("Callback unexpectedly closed");
+			conn.read_i32().await.expect("Callback unexpectedly closed");
 			let _ = req.send(());
 		}
 	}
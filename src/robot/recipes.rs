@@ -1,11 +1,14 @@
-use bytes::{BufMut, BytesMut};
-use color_eyre::eyre::{Ok, Result};
+use bytes::{Buf, BufMut, BytesMut};
+use color_eyre::eyre::{ensure, Ok, Result};
 use std::fmt::{Display, Write};
 use strum_macros::EnumIter;
 
 const INT_REGISTER_OFFSET: u8 = 24;
 const DOUBLE_REGISTER_OFFSET: u8 = 24;
 
+// output recipe, the field order has to match `RobotState::deserialize`
+pub const STATE_OUTPUTS: &str = "timestamp,actual_TCP_pose";
+
 #[derive(Debug, Clone, Copy)]
 pub enum Recipe {
 	Connection {
@@ -55,6 +58,33 @@ impl Recipe {
 	}
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct RobotState {
+	// seconds since the controller started
+	pub timestamp: f64,
+	pub tcp_pose: [f64; 6],
+}
+
+impl RobotState {
+	// data package payload, after the recipe id
+	pub fn deserialize(mut bytes: &[u8]) -> Result<Self> {
+		ensure!(
+			bytes.len() >= 7 * 8,
+			"Short RTDE state package ({} bytes)",
+			bytes.len()
+		);
+		let timestamp = bytes.get_f64();
+		let mut tcp_pose = [0.; 6];
+		for p in &mut tcp_pose {
+			*p = bytes.get_f64();
+		}
+		Ok(Self {
+			timestamp,
+			tcp_pose,
+		})
+	}
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, EnumIter)]
 pub enum RecipeId {
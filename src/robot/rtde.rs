@@ -13,7 +13,7 @@ use crate::robot::callback::CALLBACK_PORT;
 
 use super::{
 	commands::RDTECommand,
-	recipes::{Recipe, RecipeId},
+	recipes::{Recipe, RecipeId, RobotState, STATE_OUTPUTS},
 };
 
 const PROTOCOL_VERSION: u16 = 2;
@@ -97,6 +97,53 @@ impl RtdeClient {
 		Ok(())
 	}
 
+	// Separate from `setup`, the outputs are read on their own connection so reading state
+	// never has to wait behind (or interleave with) commands.
+	pub async fn setup_outputs(&mut self, frequency: f64) -> Result<()> {
+		self.request_protocol().await?;
+		let mut bytes = BytesMut::new();
+		bytes.put_u16(0);
+		bytes.put_u8(RDTECommand::ControlPackageSetupOutputs as u8);
+		bytes.put_f64(frequency);
+		bytes.put_slice(STATE_OUTPUTS.as_bytes());
+		let payload_len = bytes.len() as u16;
+		bytes[..2].copy_from_slice(&payload_len.to_be_bytes());
+		self.conn.write_all(&bytes).await?;
+
+		let (command, res_buf) = self.read_package().await?;
+		if command != RDTECommand::ControlPackageSetupOutputs as u8 {
+			bail!("Unexpected RTDE package {command} while setting up outputs");
+		}
+		let res_str = str::from_utf8(res_buf.get(1..).unwrap_or_default())
+			.expect("Bad recipe response string!");
+		if res_str.split(',').any(|res| res == "NOT_FOUND") {
+			bail!("Came across NOT_FOUND when setting up outputs {STATE_OUTPUTS}!");
+		}
+		self.start().await
+	}
+
+	pub async fn read_state(&mut self) -> Result<RobotState> {
+		loop {
+			let (command, payload) = self.read_package().await?;
+			// text messages and the like can be interleaved with data
+			if command == RDTECommand::DataPackage as u8 {
+				return RobotState::deserialize(payload.get(1..).unwrap_or_default());
+			}
+		}
+	}
+
+	async fn read_package(&mut self) -> Result<(u8, Vec<u8>)> {
+		let mut header = [0u8; 3];
+		self.conn.read_exact(&mut header).await?;
+		let len = u16::from_be_bytes([header[0], header[1]]) as usize;
+		if len < 3 {
+			bail!("Bad RTDE package length {len}");
+		}
+		let mut payload = vec![0; len - 3];
+		self.conn.read_exact(&mut payload).await?;
+		Ok((header[2], payload))
+	}
+
 	async fn start(&mut self) -> Result<()> {
 		let mut bytes = BytesMut::with_capacity(5);
 		bytes.put_u16(3);
@@ -104,7 +151,7 @@ impl RtdeClient {
 		self.conn.write_all(&bytes).await?;
 		let mut res_buff = [0u8; 4];
 		self.conn.read_exact(&mut res_buff).await?;
-		if res_buff[1] != 1 {
+		if res_buff[3] != 1 {
 			bail!("UR RTDE protocol didn't accept starting");
 		}
 		Ok(())
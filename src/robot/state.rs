@@ -0,0 +1,130 @@
+use std::{
+	collections::VecDeque,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use nix::time::{clock_gettime, ClockId};
+use tokio::task::JoinHandle;
+
+use crate::geometry::Pose;
+
+use super::rtde::RtdeClient;
+
+pub const STATE_FREQUENCY: f64 = 125.;
+// about 10s of history at 125Hz
+const HISTORY_LEN: usize = 1250;
+const OFFSET_WINDOW: usize = 250;
+
+// Same clock V4L2 stamps frames with.
+pub fn monotonic_now() -> Duration {
+	clock_gettime(ClockId::CLOCK_MONOTONIC)
+		.map(Duration::from)
+		.expect("CLOCK_MONOTONIC should always be available")
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PoseSample {
+	// controller time, seconds
+	pub timestamp: f64,
+	// host CLOCK_MONOTONIC when the package arrived
+	pub received: Duration,
+	pub pose: Pose,
+}
+
+#[derive(Default)]
+struct History {
+	samples: VecDeque<PoseSample>,
+	offsets: VecDeque<f64>,
+}
+
+impl History {
+	fn push(&mut self, sample: PoseSample) {
+		self.samples.push_back(sample);
+		if self.samples.len() > HISTORY_LEN {
+			self.samples.pop_front();
+		}
+		self.offsets
+			.push_back(sample.received.as_secs_f64() - sample.timestamp);
+		if self.offsets.len() > OFFSET_WINDOW {
+			self.offsets.pop_front();
+		}
+	}
+
+	// Every package arrives some latency after it was stamped, never before, so the
+	// smallest recent host - controller difference is the best estimate of the offset.
+	fn offset(&self) -> Option<f64> {
+		self.offsets.iter().copied().reduce(f64::min)
+	}
+}
+
+// Keeps a short history of TCP poses streamed over RTDE, so a pose can be looked up for
+// any recent host timestamp (such as a frame's).
+pub struct PoseTracker {
+	history: Arc<Mutex<History>>,
+	task: JoinHandle<()>,
+}
+
+impl PoseTracker {
+	pub fn spawn(mut rtde: RtdeClient) -> Self {
+		let history = Arc::new(Mutex::new(History::default()));
+		let task = tokio::spawn({
+			let history = history.clone();
+			async move {
+				loop {
+					let state = match rtde.read_state().await {
+						Ok(state) => state,
+						Err(e) => {
+							eprintln!("RTDE state stream stopped: {e}");
+							break;
+						}
+					};
+					let received = monotonic_now();
+					history.lock().unwrap().push(PoseSample {
+						timestamp: state.timestamp,
+						received,
+						pose: Pose::from_ur(state.tcp_pose),
+					});
+				}
+			}
+		});
+		Self { history, task }
+	}
+
+	// host CLOCK_MONOTONIC - controller time, seconds
+	pub fn clock_offset(&self) -> Option<f64> {
+		self.history.lock().unwrap().offset()
+	}
+
+	pub fn latest(&self) -> Option<PoseSample> {
+		self.history.lock().unwrap().samples.back().copied()
+	}
+
+	pub fn controller_time(&self, host: Duration) -> Option<f64> {
+		Some(host.as_secs_f64() - self.clock_offset()?)
+	}
+
+	// None if `host` is older than the history or newer than the latest sample
+	pub fn pose_at(&self, host: Duration) -> Option<Pose> {
+		let history = self.history.lock().unwrap();
+		let t = host.as_secs_f64() - history.offset()?;
+		let samples = &history.samples;
+		let (first, last) = (samples.front()?, samples.back()?);
+		if t < first.timestamp || t > last.timestamp {
+			return None;
+		}
+		let i = samples.partition_point(|s| s.timestamp <= t);
+		if i == samples.len() {
+			return Some(last.pose);
+		}
+		let (a, b) = (&samples[i - 1], &samples[i]);
+		let frac = (t - a.timestamp) / (b.timestamp - a.timestamp);
+		Some(a.pose.interpolate(&b.pose, frac))
+	}
+}
+
+impl Drop for PoseTracker {
+	fn drop(&mut self) {
+		self.task.abort();
+	}
+}
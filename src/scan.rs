@@ -0,0 +1,85 @@
+#![allow(dead_code)]
+
+pub mod export;
+pub mod session;
+pub mod viewpoints;
+
+use std::time::Duration;
+
+use color_eyre::eyre::{ensure, Result};
+use tokio::time::sleep;
+
+use crate::{
+	camera::{format::fourcc_name, frame::TimestampClock, source::FrameSource, MJPEG_FMT},
+	robot::Robot,
+};
+
+pub use export::{export, ExportFormat};
+pub use session::{CaptureRecord, Session};
+pub use viewpoints::{Pattern, ScanPlan};
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScanSettings {
+	pub speed: f64,
+	pub acceleration: f64,
+	pub settle: Duration,
+	// frames queued while the arm was still moving, dropped before capturing
+	pub flush_frames: usize,
+}
+
+impl Default for ScanSettings {
+	fn default() -> Self {
+		Self {
+			speed: 0.15,
+			acceleration: 0.4,
+			settle: Duration::from_millis(400),
+			flush_frames: 4,
+		}
+	}
+}
+
+// Visits every viewpoint that doesn't have a capture yet, so calling this again on a
+// reopened session picks up where an interrupted run stopped.
+pub async fn run(
+	session: &mut Session,
+	robot: &mut Robot,
+	source: &mut impl FrameSource,
+	settings: ScanSettings,
+) -> Result<()> {
+	// frames are stored and exported as they come, so they have to be JPEGs already
+	let format = source.format().format;
+	ensure!(
+		format == MJPEG_FMT,
+		"Scans need an MJPEG camera, this one streams {}",
+		fourcc_name(format)
+	);
+	let remaining = session.remaining();
+	println!(
+		"{} of {} viewpoints left to capture",
+		remaining.len(),
+		session.len()
+	);
+	let flange_from_camera = session.camera_to_flange().inverse();
+	for index in remaining {
+		let flange = session.viewpoint(index).compose(&flange_from_camera);
+		robot
+			.move_l(flange, settings.speed, settings.acceleration)
+			.await?;
+		sleep(settings.settle).await;
+		for _ in 0..settings.flush_frames {
+			source.read_frame(|_, _| ()).await?;
+		}
+		let (image, info) = source
+			.read_frame(|data, info| (data.to_vec(), info))
+			.await?;
+		ensure!(
+			info.timestamp_clock == TimestampClock::Monotonic,
+			"Camera timestamps aren't CLOCK_MONOTONIC, can't pair them with robot poses"
+		);
+		// where the arm actually was when the frame was exposed
+		let measured = robot.pose_at(info.timestamp).await?;
+		session.record(index, &image, measured, info)?;
+		println!("Captured viewpoint {index}");
+	}
+	Ok(())
+}
@@ -0,0 +1,181 @@
+use std::{
+	fmt::Write as _,
+	fs,
+	path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use color_eyre::eyre::{ensure, Result};
+use nalgebra::{Matrix4, Vector3, Vector4};
+use serde::Serialize;
+
+use crate::{calibration::intrinsics::CameraIntrinsics, geometry::Pose};
+
+use super::{CaptureRecord, Session};
+
+const IMAGE_DIR: &str = "images";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+	Colmap,
+	Nerf,
+	All,
+}
+
+// Copies the captured images to `<out>/images` and writes the poses next to them, as
+// `<out>/sparse/0/*.txt` for COLMAP and/or `<out>/transforms.json` for Nerfstudio and
+// instant-ngp.
+pub fn export(
+	session: &Session,
+	intrinsics: &CameraIntrinsics,
+	out: impl AsRef<Path>,
+	format: ExportFormat,
+) -> Result<()> {
+	let out = out.as_ref();
+	let mut captures: Vec<&CaptureRecord> = session.manifest().captures.iter().collect();
+	ensure!(!captures.is_empty(), "Session has no captures to export");
+	captures.sort_by_key(|c| c.index);
+
+	fs::create_dir_all(out.join(IMAGE_DIR))?;
+	for capture in &captures {
+		fs::copy(
+			session.dir().join(&capture.image),
+			out.join(IMAGE_DIR).join(&capture.image),
+		)?;
+	}
+	if matches!(format, ExportFormat::Colmap | ExportFormat::All) {
+		write_colmap(&captures, intrinsics, out.join("sparse").join("0"))?;
+	}
+	if matches!(format, ExportFormat::Nerf | ExportFormat::All) {
+		let centre = Vector3::from(session.manifest().plan.centre);
+		write_transforms(&captures, intrinsics, centre, out.join("transforms.json"))?;
+	}
+	Ok(())
+}
+
+// COLMAP shares our camera convention (x right, y down, z forward) but stores world to
+// camera, so the base frame poses only need inverting. The model has no points, it's
+// meant as the input to point_triangulator.
+pub fn write_colmap(
+	captures: &[&CaptureRecord],
+	intrinsics: &CameraIntrinsics,
+	dir: impl AsRef<Path>,
+) -> Result<()> {
+	let dir = dir.as_ref();
+	fs::create_dir_all(dir)?;
+
+	let CameraIntrinsics {
+		width,
+		height,
+		fx,
+		fy,
+		cx,
+		cy,
+		distortion: [k1, k2, p1, p2, k3],
+	} = *intrinsics;
+	let mut cameras = String::from("# CAMERA_ID, MODEL, WIDTH, HEIGHT, PARAMS[]\n");
+	// OPENCV has no k3, FULL_OPENCV does (followed by the rational k4..k6)
+	if k3 == 0. {
+		writeln!(
+			cameras,
+			"1 OPENCV {width} {height} {fx} {fy} {cx} {cy} {k1} {k2} {p1} {p2}"
+		)?;
+	} else {
+		writeln!(
+			cameras,
+			"1 FULL_OPENCV {width} {height} {fx} {fy} {cx} {cy} {k1} {k2} {p1} {p2} {k3} 0 0 0"
+		)?;
+	}
+
+	let mut images = String::from(
+		"# IMAGE_ID, QW, QX, QY, QZ, TX, TY, TZ, CAMERA_ID, NAME\n# POINTS2D[] as (X, Y, POINT3D_ID)\n",
+	);
+	for (id, capture) in captures.iter().enumerate() {
+		let world_to_camera = Pose::from_ur(capture.camera).inverse();
+		let [qw, qx, qy, qz] = world_to_camera.rotation.to_quaternion();
+		let t = world_to_camera.translation;
+		writeln!(
+			images,
+			"{} {qw} {qx} {qy} {qz} {} {} {} 1 {}\n",
+			id + 1,
+			t.x,
+			t.y,
+			t.z,
+			capture.image
+		)?;
+	}
+
+	fs::write(dir.join("cameras.txt"), cameras)?;
+	fs::write(dir.join("images.txt"), images)?;
+	fs::write(dir.join("points3D.txt"), "")?;
+	Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct Transforms {
+	camera_model: &'static str,
+	fl_x: f64,
+	fl_y: f64,
+	cx: f64,
+	cy: f64,
+	w: u32,
+	h: u32,
+	k1: f64,
+	k2: f64,
+	k3: f64,
+	p1: f64,
+	p2: f64,
+	// instant-ngp only, how far past the unit cube the scene may extend
+	aabb_scale: u32,
+	frames: Vec<TransformFrame>,
+}
+
+#[derive(Debug, Serialize)]
+struct TransformFrame {
+	file_path: PathBuf,
+	transform_matrix: [[f64; 4]; 4],
+}
+
+// transforms.json wants camera to world in the OpenGL convention (x right, y up, z back),
+// so y and z flip. The scan centre becomes the origin, keeping base z as up, so the
+// object sits where instant-ngp expects the scene to be.
+pub fn write_transforms(
+	captures: &[&CaptureRecord],
+	intrinsics: &CameraIntrinsics,
+	centre: Vector3<f64>,
+	path: impl AsRef<Path>,
+) -> Result<()> {
+	let opencv_to_opengl = Matrix4::from_diagonal(&Vector4::new(1., -1., -1., 1.));
+	let frames = captures
+		.iter()
+		.map(|capture| {
+			let mut camera = Pose::from_ur(capture.camera);
+			camera.translation -= centre;
+			let m = camera.matrix() * opencv_to_opengl;
+			TransformFrame {
+				file_path: Path::new(IMAGE_DIR).join(&capture.image),
+				transform_matrix: std::array::from_fn(|r| std::array::from_fn(|c| m[(r, c)])),
+			}
+		})
+		.collect();
+
+	let [k1, k2, p1, p2, k3] = intrinsics.distortion;
+	let transforms = Transforms {
+		camera_model: "OPENCV",
+		fl_x: intrinsics.fx,
+		fl_y: intrinsics.fy,
+		cx: intrinsics.cx,
+		cy: intrinsics.cy,
+		w: intrinsics.width,
+		h: intrinsics.height,
+		k1,
+		k2,
+		k3,
+		p1,
+		p2,
+		aabb_scale: 4,
+		frames,
+	};
+	fs::write(path, serde_json::to_string_pretty(&transforms)?)?;
+	Ok(())
+}
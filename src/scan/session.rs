@@ -0,0 +1,135 @@
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{camera::FrameInfo, geometry::Pose};
+
+use super::viewpoints::ScanPlan;
+
+const MANIFEST: &str = "session.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRecord {
+	pub index: usize,
+	// relative to the session directory
+	pub image: String,
+	// poses in the robot base frame, [x, y, z, rx, ry, rz]
+	pub flange: [f64; 6],
+	pub camera: [f64; 6],
+	// host CLOCK_MONOTONIC seconds and driver sequence number of the frame
+	#[serde(default)]
+	pub timestamp: f64,
+	#[serde(default)]
+	pub sequence: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+	pub plan: ScanPlan,
+	pub camera_to_flange: [f64; 6],
+	// planned camera poses, in capture order
+	pub viewpoints: Vec<[f64; 6]>,
+	pub captures: Vec<CaptureRecord>,
+}
+
+pub struct Session {
+	dir: PathBuf,
+	manifest: Manifest,
+}
+
+impl Session {
+	pub fn create(dir: impl AsRef<Path>, plan: ScanPlan, camera_to_flange: Pose) -> Result<Self> {
+		let dir = dir.as_ref().to_path_buf();
+		if dir.join(MANIFEST).exists() {
+			bail!("{} already contains a scan session", dir.display());
+		}
+		fs::create_dir_all(&dir)?;
+		let manifest = Manifest {
+			plan,
+			camera_to_flange: camera_to_flange.to_ur(),
+			viewpoints: plan.viewpoints().into_iter().map(Pose::to_ur).collect(),
+			captures: Vec::new(),
+		};
+		let session = Self { dir, manifest };
+		session.save()?;
+		Ok(session)
+	}
+
+	pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+		let dir = dir.as_ref().to_path_buf();
+		let path = dir.join(MANIFEST);
+		let manifest = serde_json::from_str(
+			&fs::read_to_string(&path).wrap_err_with(|| format!("Reading {}", path.display()))?,
+		)?;
+		Ok(Self { dir, manifest })
+	}
+
+	pub fn exists(dir: impl AsRef<Path>) -> bool {
+		dir.as_ref().join(MANIFEST).exists()
+	}
+
+	pub fn dir(&self) -> &Path {
+		&self.dir
+	}
+
+	pub fn manifest(&self) -> &Manifest {
+		&self.manifest
+	}
+
+	pub fn camera_to_flange(&self) -> Pose {
+		Pose::from_ur(self.manifest.camera_to_flange)
+	}
+
+	pub fn len(&self) -> usize {
+		self.manifest.viewpoints.len()
+	}
+
+	pub fn viewpoint(&self, index: usize) -> Pose {
+		Pose::from_ur(self.manifest.viewpoints[index])
+	}
+
+	// viewpoints without a capture on disk yet, in capture order
+	pub fn remaining(&self) -> Vec<usize> {
+		(0..self.len())
+			.filter(|&i| !self.manifest.captures.iter().any(|c| c.index == i))
+			.collect()
+	}
+
+	pub fn record(
+		&mut self,
+		index: usize,
+		image: &[u8],
+		flange: Pose,
+		info: FrameInfo,
+	) -> Result<()> {
+		ensure!(
+			index < self.len(),
+			"Viewpoint {index} isn't part of the plan"
+		);
+		let name = format!("{index:04}.jpg");
+		fs::write(self.dir.join(&name), image)?;
+		let camera = flange.compose(&self.camera_to_flange());
+		self.manifest.captures.retain(|c| c.index != index);
+		self.manifest.captures.push(CaptureRecord {
+			index,
+			image: name,
+			flange: flange.to_ur(),
+			camera: camera.to_ur(),
+			timestamp: info.timestamp.as_secs_f64(),
+			sequence: info.sequence,
+		});
+		self.save()
+	}
+
+	// written to a temporary file first so an interruption can't leave a torn manifest
+	fn save(&self) -> Result<()> {
+		let tmp = self.dir.join(format!("{MANIFEST}.tmp"));
+		fs::write(&tmp, serde_json::to_string_pretty(&self.manifest)?)?;
+		fs::rename(tmp, self.dir.join(MANIFEST))?;
+		Ok(())
+	}
+}
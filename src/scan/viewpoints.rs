@@ -0,0 +1,132 @@
+use std::f64::consts::{PI, TAU};
+
+use clap::ValueEnum;
+use nalgebra::{Matrix3, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::{Pose, Rotation};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum Pattern {
+	// evenly spread over the band of the hemisphere (golden angle spiral on equal areas)
+	Hemisphere,
+	// a single continuous spiral from the lowest elevation up to the highest
+	Spiral,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ScanPlan {
+	// object centre in the robot base frame, metres
+	pub centre: [f64; 3],
+	pub radius: f64,
+	pub pattern: Pattern,
+	pub count: usize,
+	// elevation band above the base xy plane, radians
+	pub min_elevation: f64,
+	pub max_elevation: f64,
+	pub turns: f64,
+}
+
+impl ScanPlan {
+	pub fn new(centre: [f64; 3], radius: f64, pattern: Pattern, count: usize) -> Self {
+		Self {
+			centre,
+			radius,
+			pattern,
+			count,
+			min_elevation: 20f64.to_radians(),
+			max_elevation: 80f64.to_radians(),
+			turns: 3.,
+		}
+	}
+
+	// Camera poses (OpenCV convention, z looking at the centre) in the order they should
+	// be visited.
+	pub fn viewpoints(&self) -> Vec<Pose> {
+		let centre = Vector3::from(self.centre);
+		let n = self.count.max(1);
+		let mut poses: Vec<Pose> = (0..n)
+			.map(|i| {
+				let t = if n == 1 {
+					0.
+				} else {
+					i as f64 / (n - 1) as f64
+				};
+				let (elevation, azimuth) = match self.pattern {
+					Pattern::Hemisphere => {
+						let (lo, hi) = (self.min_elevation.sin(), self.max_elevation.sin());
+						let golden_angle = PI * (3. - 5f64.sqrt());
+						((lo + t * (hi - lo)).asin(), i as f64 * golden_angle)
+					}
+					Pattern::Spiral => (
+						self.min_elevation + t * (self.max_elevation - self.min_elevation),
+						t * self.turns * TAU,
+					),
+				};
+				let direction = Vector3::new(
+					elevation.cos() * azimuth.cos(),
+					elevation.cos() * azimuth.sin(),
+					elevation.sin(),
+				);
+				look_at(centre + direction * self.radius, centre)
+			})
+			.collect();
+		order_for_travel(&mut poses, self.radius);
+		poses
+	}
+}
+
+// Camera at `eye` looking at `target`, with the image's "down" pointing as close to the
+// base's -z as possible.
+pub fn look_at(eye: Vector3<f64>, target: Vector3<f64>) -> Pose {
+	let z = (target - eye).normalize();
+	let up = if z.cross(&Vector3::z()).norm() < 1e-6 {
+		// looking straight down/up, any horizontal reference will do
+		Vector3::x()
+	} else {
+		Vector3::z()
+	};
+	let y = (-up + z * up.dot(&z)).normalize();
+	let x = y.cross(&z);
+	let rotation = Matrix3::from_columns(&[x, y, z]);
+	Pose::new(eye, Rotation::from_matrix(&rotation))
+}
+
+// Nearest neighbour tour followed by 2-opt, weighting rotation by `radius` so a viewpoint
+// swing costs about as much as the arc the camera travels.
+pub fn order_for_travel(poses: &mut Vec<Pose>, radius: f64) {
+	if poses.len() < 3 {
+		return;
+	}
+	let cost = |a: &Pose, b: &Pose| a.distance(b, radius);
+
+	let mut remaining = std::mem::take(poses);
+	let mut tour = vec![remaining.swap_remove(0)];
+	while !remaining.is_empty() {
+		let last = tour[tour.len() - 1];
+		let (next, _) = remaining
+			.iter()
+			.enumerate()
+			.map(|(i, p)| (i, cost(&last, p)))
+			.min_by(|a, b| a.1.total_cmp(&b.1))
+			.expect("remaining isn't empty");
+		tour.push(remaining.swap_remove(next));
+	}
+
+	// open path, so there is no edge back to the start
+	let mut improved = true;
+	while improved {
+		improved = false;
+		for i in 0..tour.len() - 2 {
+			for j in i + 2..tour.len() - 1 {
+				let before = cost(&tour[i], &tour[i + 1]) + cost(&tour[j], &tour[j + 1]);
+				let after = cost(&tour[i], &tour[j]) + cost(&tour[i + 1], &tour[j + 1]);
+				if after + 1e-9 < before {
+					tour[i + 1..=j].reverse();
+					improved = true;
+				}
+			}
+		}
+	}
+	*poses = tour;
+}
@@ -1,21 +1,28 @@
 use std::{
 	ffi::CString,
-	mem,
+	io, mem,
+	os::unix::ffi::OsStrExt,
+	path::Path,
 	ptr::{self, null, null_mut, write_bytes},
 	time::Instant,
 };
 
 use ffmpeg_sys_next::{
-	av_frame_alloc, av_frame_free, av_frame_get_buffer, av_frame_make_writable,
-	av_interleaved_write_frame, av_log_set_level, av_packet_alloc, av_packet_unref,
-	av_write_trailer, avcodec_alloc_context3, avcodec_find_encoder, avcodec_free_context,
-	avcodec_open2, avcodec_parameters_from_context, avcodec_receive_packet, avcodec_send_frame,
-	avformat_alloc_output_context2, avformat_free_context, avformat_network_deinit,
-	avformat_network_init, avformat_new_stream, avformat_write_header, avio_close, avio_open,
-	memset, AVCodec, AVCodecContext, AVColorRange, AVFormatContext, AVFrame, AVPacket,
-	AVPixelFormat, AVRational, AVStream, AVIO_FLAG_WRITE, AV_LOG_WARNING,
+	av_find_best_stream, av_frame_alloc, av_frame_free, av_frame_get_buffer,
+	av_frame_make_writable, av_interleaved_write_frame, av_log_set_level, av_packet_alloc,
+	av_packet_free, av_packet_rescale_ts, av_packet_unref, av_read_frame, av_write_trailer,
+	avcodec_alloc_context3, avcodec_find_encoder, avcodec_free_context, avcodec_open2,
+	avcodec_parameters_from_context, avcodec_parameters_to_context, avcodec_receive_frame,
+	avcodec_receive_packet, avcodec_send_frame, avcodec_send_packet,
+	avformat_alloc_output_context2, avformat_close_input, avformat_find_stream_info,
+	avformat_free_context, avformat_network_deinit, avformat_network_init, avformat_new_stream,
+	avformat_open_input, avformat_write_header, avio_close, avio_open, memset, AVCodec,
+	AVCodecContext, AVColorRange, AVFormatContext, AVFrame, AVMediaType, AVPacket, AVPixelFormat,
+	AVRational, AVStream, AVERROR, AVERROR_EOF, AVIO_FLAG_WRITE, AV_LOG_WARNING,
 };
 
+use crate::camera::format::Fraction;
+
 pub struct VideoContext {}
 
 impl VideoContext {
@@ -41,13 +48,26 @@ pub struct Encoder {
 	format_ctx: *mut AVFormatContext,
 	codec_ctx: *mut AVCodecContext,
 	frame: *mut AVFrame,
+	time_base: AVRational,
+	// what the muxer picked in avformat_write_header, 1/1000 for matroska
+	stream_time_base: AVRational,
 }
 
 impl Encoder {
-	pub fn new() -> Self {
+	// Writes a Matroska file to `path`. `frame_interval` is the camera's, pts passed to
+	// `encode` count frames at that rate.
+	pub fn new(path: &Path, width: u32, height: u32, frame_interval: Fraction) -> Self {
+		// paths from the command line can't have NULs in them
+		let url = CString::new(path.as_os_str().as_bytes()).expect("path without NULs");
 		unsafe {
-			let time_base = AVRational { num: 1, den: 25 };
-			let framerate = AVRational { num: 25, den: 1 };
+			let time_base = AVRational {
+				num: frame_interval.numerator as i32,
+				den: frame_interval.denominator as i32,
+			};
+			let framerate = AVRational {
+				num: time_base.den,
+				den: time_base.num,
+			};
 
 			let mut format_ctx = null_mut();
 			avformat_alloc_output_context2(&mut format_ctx, null(), c"matroska".as_ptr(), null());
@@ -65,9 +85,9 @@ impl Encoder {
 				panic!("Failed getting codec context");
 			}
 
-			(*video_stream).time_base = AVRational { num: 1, den: 25 };
-			(*codec_ctx).width = 1920;
-			(*codec_ctx).height = 1080;
+			(*video_stream).time_base = time_base;
+			(*codec_ctx).width = width as i32;
+			(*codec_ctx).height = height as i32;
 			(*codec_ctx).pix_fmt = AVPixelFormat::AV_PIX_FMT_YUV444P;
 			(*codec_ctx).color_range = AVColorRange::AVCOL_RANGE_JPEG;
 			(*codec_ctx).time_base = time_base;
@@ -79,16 +99,13 @@ impl Encoder {
 			avcodec_open2(codec_ctx, codec, null_mut());
 			avcodec_parameters_from_context((*video_stream).codecpar, codec_ctx);
 
-			avio_open(
-				&mut (*format_ctx).pb,
-				c"output.mkv".as_ptr(),
-				AVIO_FLAG_WRITE,
-			);
+			avio_open(&mut (*format_ctx).pb, url.as_ptr(), AVIO_FLAG_WRITE);
 			avformat_write_header(format_ctx, null_mut());
+			let stream_time_base = (*video_stream).time_base;
 
 			let frame = av_frame_alloc();
-			(*frame).width = 1920;
-			(*frame).height = 1080;
+			(*frame).width = width as i32;
+			(*frame).height = height as i32;
 			(*frame).format = AVPixelFormat::AV_PIX_FMT_YUV444P as i32;
 			(*frame).color_range = AVColorRange::AVCOL_RANGE_JPEG;
 			av_frame_get_buffer(frame, 32);
@@ -97,27 +114,43 @@ impl Encoder {
 				format_ctx,
 				codec_ctx,
 				frame,
+				time_base,
+				stream_time_base,
 			}
 		}
 	}
 
+	// Full resolution planes, width * height bytes each
 	pub fn encode(&mut self, pts: i64, y: &[u8], cb: &[u8], cr: &[u8]) {
 		unsafe {
 			av_frame_make_writable(self.frame);
-			ptr::copy_nonoverlapping(y.as_ptr(), (*self.frame).data[0], y.len());
-			ptr::copy_nonoverlapping(cb.as_ptr(), (*self.frame).data[0], cb.len());
-			ptr::copy_nonoverlapping(cr.as_ptr(), (*self.frame).data[0], cr.len());
+			for (plane, src) in [y, cb, cr].into_iter().enumerate() {
+				self.copy_plane(plane, src);
+			}
 			// let image = create_image(i as u8);
 			// ptr::copy_nonoverlapping(image, (*frame).data[0], 1920 * 1080 * 3);
-			(*self.frame).pts = pts * 40;
-			(*self.frame).time_base = AVRational { num: 1, den: 25 };
+			(*self.frame).pts = pts;
+			(*self.frame).time_base = self.time_base;
 
 			avcodec_send_frame(self.codec_ctx, self.frame);
-			let packet = av_packet_alloc();
+			let mut packet = av_packet_alloc();
 			while avcodec_receive_packet(self.codec_ctx, packet) == 0 {
+				av_packet_rescale_ts(packet, self.time_base, self.stream_time_base);
 				av_interleaved_write_frame(self.format_ctx, packet);
 				av_packet_unref(packet);
 			}
+			av_packet_free(&mut packet);
+		}
+	}
+
+	// ffmpeg pads rows out to linesize, which can be wider than the frame
+	unsafe fn copy_plane(&mut self, plane: usize, src: &[u8]) {
+		let width = (*self.frame).width as usize;
+		let height = (*self.frame).height as usize;
+		let linesize = (*self.frame).linesize[plane] as usize;
+		let dst = (*self.frame).data[plane];
+		for (row, line) in src.chunks_exact(width).take(height).enumerate() {
+			ptr::copy_nonoverlapping(line.as_ptr(), dst.add(row * linesize), width);
 		}
 	}
 
@@ -126,12 +159,15 @@ impl Encoder {
 			format_ctx,
 			mut codec_ctx,
 			mut frame,
+			time_base,
+			stream_time_base,
 		} = self;
 
 		unsafe {
 			avcodec_send_frame(codec_ctx, null_mut());
 			let mut packet: AVPacket = mem::zeroed();
 			while avcodec_receive_packet(codec_ctx, &mut packet) == 0 {
+				av_packet_rescale_ts(&mut packet, time_base, stream_time_base);
 				av_interleaved_write_frame(format_ctx, &mut packet);
 				av_packet_unref(&mut packet);
 			}
@@ -145,6 +181,169 @@ impl Encoder {
 	}
 }
 
+// Reads back what `Encoder` wrote, or anything else ffmpeg decodes to 4:4:4
+pub struct Decoder {
+	format_ctx: *mut AVFormatContext,
+	codec_ctx: *mut AVCodecContext,
+	frame: *mut AVFrame,
+	packet: *mut AVPacket,
+	stream: i32,
+	draining: bool,
+	pub width: u32,
+	pub height: u32,
+	pub frame_interval: Fraction,
+}
+
+impl Decoder {
+	pub fn open(path: &Path) -> io::Result<Self> {
+		let url = CString::new(path.as_os_str().as_bytes())
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+		unsafe {
+			let mut format_ctx = null_mut();
+			check(
+				avformat_open_input(&mut format_ctx, url.as_ptr(), null(), null_mut()),
+				"Opening the video",
+			)?;
+			// owns format_ctx from here, so an early return closes it
+			let mut decoder = Self {
+				format_ctx,
+				codec_ctx: null_mut(),
+				frame: av_frame_alloc(),
+				packet: av_packet_alloc(),
+				stream: 0,
+				draining: false,
+				width: 0,
+				height: 0,
+				frame_interval: Fraction::new(1, 30),
+			};
+			check(
+				avformat_find_stream_info(format_ctx, null_mut()),
+				"Reading the stream info",
+			)?;
+			let mut codec = null();
+			decoder.stream = check(
+				av_find_best_stream(
+					format_ctx,
+					AVMediaType::AVMEDIA_TYPE_VIDEO,
+					-1,
+					-1,
+					&mut codec,
+					0,
+				),
+				"Finding a video stream",
+			)?;
+			let stream = *(*format_ctx).streams.add(decoder.stream as usize);
+
+			decoder.codec_ctx = avcodec_alloc_context3(codec);
+			check(
+				avcodec_parameters_to_context(decoder.codec_ctx, (*stream).codecpar),
+				"Reading the codec parameters",
+			)?;
+			check(
+				avcodec_open2(decoder.codec_ctx, codec, null_mut()),
+				"Opening the decoder",
+			)?;
+			let pix_fmt = (*decoder.codec_ctx).pix_fmt;
+			if ![
+				AVPixelFormat::AV_PIX_FMT_YUV444P,
+				AVPixelFormat::AV_PIX_FMT_YUVJ444P,
+			]
+			.contains(&pix_fmt)
+			{
+				return Err(io::Error::new(
+					io::ErrorKind::Unsupported,
+					format!("Only 4:4:4 video can be decoded, this is {pix_fmt:?}"),
+				));
+			}
+			decoder.width = (*decoder.codec_ctx).width as u32;
+			decoder.height = (*decoder.codec_ctx).height as u32;
+			let rate = (*stream).avg_frame_rate;
+			if rate.num > 0 && rate.den > 0 {
+				decoder.frame_interval = Fraction::new(rate.den as u32, rate.num as u32);
+			}
+			Ok(decoder)
+		}
+	}
+
+	// Y, Cb and Cr planes one after the other into `out`, each width * height bytes.
+	// false once the video has ended.
+	pub fn decode(&mut self, out: &mut Vec<u8>) -> io::Result<bool> {
+		unsafe {
+			loop {
+				let ret = avcodec_receive_frame(self.codec_ctx, self.frame);
+				if ret == 0 {
+					self.copy_planes(out);
+					return Ok(true);
+				}
+				if ret == AVERROR_EOF {
+					return Ok(false);
+				}
+				if ret != AVERROR(nix::libc::EAGAIN) {
+					check(ret, "Decoding a frame")?;
+				}
+
+				// the decoder wants more input
+				let ret = av_read_frame(self.format_ctx, self.packet);
+				if ret == AVERROR_EOF {
+					if !self.draining {
+						// flushes the frames the decoder is still holding
+						check(
+							avcodec_send_packet(self.codec_ctx, null()),
+							"Flushing the decoder",
+						)?;
+						self.draining = true;
+					}
+					continue;
+				}
+				check(ret, "Reading a packet")?;
+				let res = if (*self.packet).stream_index == self.stream {
+					check(
+						avcodec_send_packet(self.codec_ctx, self.packet),
+						"Decoding a packet",
+					)
+				} else {
+					Ok(0)
+				};
+				av_packet_unref(self.packet);
+				res?;
+			}
+		}
+	}
+
+	unsafe fn copy_planes(&mut self, out: &mut Vec<u8>) {
+		let width = self.width as usize;
+		let height = self.height as usize;
+		out.clear();
+		out.reserve(width * height * 3);
+		for plane in 0..3 {
+			let linesize = (*self.frame).linesize[plane] as usize;
+			let src = (*self.frame).data[plane];
+			for row in 0..height {
+				out.extend_from_slice(std::slice::from_raw_parts(src.add(row * linesize), width));
+			}
+		}
+	}
+}
+
+impl Drop for Decoder {
+	fn drop(&mut self) {
+		unsafe {
+			av_packet_free(&mut self.packet);
+			av_frame_free(&mut self.frame);
+			avcodec_free_context(&mut self.codec_ctx);
+			avformat_close_input(&mut self.format_ctx);
+		}
+	}
+}
+
+fn check(ret: i32, what: &str) -> io::Result<i32> {
+	if ret < 0 {
+		Err(io::Error::other(format!("{what} failed ({ret})")))
+	} else {
+		Ok(ret)
+	}
+}
+
 pub fn test() {
 	unsafe {
 		let time_base = AVRational { num: 1, den: 25 };